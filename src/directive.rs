@@ -0,0 +1,657 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::{Media, MediaKind, Reporter, RenderOptions, Span, SynElement, SynError, Track};
+
+
+// Types
+
+/// A kind of `.name` line. Directives are written as `.name arg|arg key=value`: `.name`, a space,
+/// the positional arguments in order, separated by `|`, and then the named arguments as
+/// space-separated `key=value` words, where `key` is one of the directive's named arguments. A
+/// value with whitespace or a `"` in it is written in double quotes, with `\"` and `\\` escapes,
+/// as in `track="talk.vtt;de;Deutsch (Schweiz)"`.
+///
+/// Within the positional arguments, `\|` is a literal `|`. That's the only escape, so other
+/// backslashes are kept as they are, as in paths like `C:\new\pic.png`, and an argument can't end
+/// in a backslash unless it's the last one. If the last one ends in a word that would read as a
+/// named argument, that word is escaped with a backslash, as in `\loop=true`. Directives without
+/// named arguments, like `.img`, read just as they did before there were other directives.
+///
+/// Register directives in `ParseOptions::directives` to have them parsed into
+/// `SynElement::Custom` elements, which render and save themselves through the directive.
+pub trait Directive: Send + Sync {
+    /// The name written after the `.`. Like named arguments, it starts with a lowercase letter and
+    /// continues with lowercase letters, digits, `-` and `_`.
+    fn name(&self) -> &str;
+
+    /// The names of the positional arguments, used in error messages.
+    fn positional(&self) -> &[&str];
+
+    /// How many of the last positional arguments may be left out.
+    fn optional(&self) -> usize {
+        0
+    }
+
+    /// The keys accepted as named arguments.
+    fn named(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Checks the arguments after they've been split up, returning a message if they're invalid.
+    fn validate(&self, _args: &DirectiveArgs) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Turns the arguments into one of the built-in elements. Returning `None`, the default, keeps
+    /// them in a `SynElement::Custom` that renders with `render` and saves with `serialize`.
+    fn to_element(&self, _args: &DirectiveArgs) -> Option<SynElement> {
+        None
+    }
+
+    fn render(&self, args: &DirectiveArgs, options: &RenderOptions) -> String;
+
+    /// The directive's line in source form.
+    fn serialize(&self, args: &DirectiveArgs) -> String {
+        args.generate_line(self.name(), self.named())
+    }
+}
+
+/// The arguments of a directive line.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct DirectiveArgs {
+    pub positional: Vec<String>,
+    /// Named arguments in the order they were written.
+    pub named: Vec<(String, String)>,
+}
+
+/// The directives known to the parser, by name. The default set has `.img`, `.figure`, `.video`,
+/// `.audio` and `.embed`.
+#[derive(Clone)]
+pub struct Directives {
+    directives: HashMap<String, Arc<dyn Directive>>,
+}
+
+/// A `key=value` word at the end of a directive's arguments.
+struct NamedWord<'a> {
+    /// Where the word starts, including any backslashes escaping it.
+    start: usize,
+    /// How many backslashes the word starts with. An escaped word is positional text.
+    escapes: usize,
+    key: &'a str,
+    value: String,
+}
+
+/// An element made by a registered directive that doesn't have a built-in element of its own.
+#[derive(Clone)]
+pub struct CustomElement {
+    directive: Arc<dyn Directive>,
+    args: DirectiveArgs,
+}
+
+
+struct ImageDirective;
+
+struct FigureDirective;
+
+struct MediaDirective(MediaKind);
+
+struct EmbedDirective;
+
+
+// Implementations
+
+impl DirectiveArgs {
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.positional.get(index).map(String::as_str)
+    }
+
+
+    /// The value of a named argument. If it was given more than once, the last one counts.
+    pub fn get_named(&self, key: &str) -> Option<&str> {
+        self.named.iter().rev().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+
+    /// Splits a directive's arguments into the named ones at the end and positional sections. The
+    /// last `optional` positional sections may be left out; any other count is an error, which we
+    /// recover from by padding with empty sections or folding extra ones into the last.
+    pub(crate) fn parse(source: &str, directive: &dyn Directive, span: Span, reporter: &mut Reporter) -> Result<Self, SynError> {
+        let names = directive.positional();
+        let mut args = DirectiveArgs::default();
+        let mut positional = source;
+        while let Some(word) = last_named_word(positional, directive.named()).filter(|word| word.escapes == 0) {
+            args.named.push((word.key.to_string(), word.value));
+            positional = positional[..word.start].strip_suffix(' ').unwrap_or_default();
+        }
+        args.named.reverse();
+        // What's left may end in an escaped word, which loses one of its backslashes.
+        let positional = match last_named_word(positional, directive.named()) {
+            Some(word) => format!("{}{}", &positional[..word.start], &positional[word.start + 1..]),
+            None => positional.to_string(),
+        };
+        if !positional.is_empty() {
+            args.positional = split_sections(&positional).into_iter().map(unescape_section).collect();
+        }
+
+        let found = args.positional.len();
+        let required = names.len().saturating_sub(directive.optional());
+        if found < required || found > names.len() {
+            if found < names.len() {
+                args.positional.resize(required, String::new());
+            } else if names.is_empty() {
+                args.positional.clear();
+            } else {
+                let last = args.positional.split_off(names.len() - 1).join("|");
+                args.positional.push(last);
+            }
+
+            let expected = match directive.optional() {
+                0 => names.len().to_string(),
+                _ => format!("{required} to {}", names.len()),
+            };
+            let error = SynError::MalformedDirective {
+                path: None,
+                line: span.line,
+                column: span.column,
+                directive: directive.name().into(),
+                message: format!("expected {expected} sections separated by '|' ({}), found {found}", names.join("|")),
+            };
+            reporter.error(error, span, Some(args.generate_sections(directive.named())))?;
+        }
+        Ok(args)
+    }
+
+
+    /// Writes the arguments out as a directive line, positional ones first.
+    pub fn generate_line(&self, name: &str, named_keys: &[&str]) -> String {
+        match self.generate_sections(named_keys).as_str() {
+            "" => format!(".{name}"),
+            sections => format!(".{name} {sections}"),
+        }
+    }
+
+
+    /// Checks that the arguments can be written out so they read back the same: no positional
+    /// section but the last may end in a backslash, since it would escape the `|` after it.
+    pub(crate) fn check_sections(&self) -> Result<(), String> {
+        match self.positional.iter().rev().skip(1).find(|section| section.ends_with('\\')) {
+            Some(section) => Err(format!("section '{section}' ends in a backslash, which would escape the `|` after it")),
+            None => Ok(()),
+        }
+    }
+
+
+    fn generate_sections(&self, named_keys: &[&str]) -> String {
+        let mut positional = self.positional.iter().map(|section| escape_section(section)).collect::<Vec<_>>().join("|");
+        if let Some(word) = last_named_word(&positional, named_keys) {
+            positional.insert(word.start, '\\');
+        }
+        let named = self.named.iter().map(|(key, value)| match value.contains(|c: char| c.is_whitespace() || c == '"') {
+            true => format!("{key}=\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            false => format!("{key}={value}"),
+        });
+        std::iter::once(positional).filter(|positional| !positional.is_empty()).chain(named).collect::<Vec<_>>().join(" ")
+    }
+}
+
+
+impl Directives {
+    /// Adds a directive, replacing any existing one with the same name.
+    pub fn register<D: Directive + 'static>(&mut self, directive: D) {
+        self.directives.insert(directive.name().to_string(), Arc::new(directive));
+    }
+
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Directive>> {
+        self.directives.get(name)
+    }
+}
+
+
+impl Default for Directives {
+    fn default() -> Self {
+        let mut directives = Self { directives: HashMap::new() };
+        directives.register(ImageDirective);
+        directives.register(FigureDirective);
+        directives.register(MediaDirective(MediaKind::Video));
+        directives.register(MediaDirective(MediaKind::Audio));
+        directives.register(EmbedDirective);
+        directives
+    }
+}
+
+
+impl fmt::Debug for Directives {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.directives.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_tuple("Directives").field(&names).finish()
+    }
+}
+
+
+impl CustomElement {
+    pub fn new(directive: Arc<dyn Directive>, args: DirectiveArgs) -> Self {
+        Self { directive, args }
+    }
+
+
+    pub fn get_name(&self) -> &str {
+        self.directive.name()
+    }
+    pub fn get_args(&self) -> &DirectiveArgs {
+        &self.args
+    }
+    pub fn get_directive(&self) -> &Arc<dyn Directive> {
+        &self.directive
+    }
+
+
+    pub(crate) fn render(&self, options: &RenderOptions) -> String {
+        self.directive.render(&self.args, options)
+    }
+
+
+    pub(crate) fn generate_line(&self) -> String {
+        self.directive.serialize(&self.args)
+    }
+
+
+    pub(crate) fn sections_mut(&mut self) -> Vec<&mut String> {
+        self.args.positional.iter_mut().chain(self.args.named.iter_mut().map(|(_, value)| value)).collect()
+    }
+}
+
+
+/// Custom elements are equal when they come from directives of the same name with the same
+/// arguments.
+impl PartialEq for CustomElement {
+    fn eq(&self, other: &Self) -> bool {
+        self.get_name() == other.get_name() && self.args == other.args
+    }
+}
+
+
+impl Eq for CustomElement {}
+
+
+impl fmt::Debug for CustomElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomElement").field("name", &self.get_name()).field("args", &self.args).finish()
+    }
+}
+
+
+impl Directive for ImageDirective {
+    fn name(&self) -> &str {
+        "img"
+    }
+
+
+    fn positional(&self) -> &[&str] {
+        &["path", "alt", "style"]
+    }
+
+
+    fn to_element(&self, args: &DirectiveArgs) -> Option<SynElement> {
+        Some(SynElement::Image { path: args.positional[0].clone(), alt: args.positional[1].clone(), style: args.positional[2].clone() })
+    }
+
+
+    fn render(&self, args: &DirectiveArgs, options: &RenderOptions) -> String {
+        self.to_element(args).map(|element| element.generate_tag_with(options)).unwrap_or_default()
+    }
+}
+
+
+impl Directive for FigureDirective {
+    fn name(&self) -> &str {
+        "figure"
+    }
+
+
+    fn positional(&self) -> &[&str] {
+        &["path", "alt", "style", "caption", "credit"]
+    }
+
+
+    fn optional(&self) -> usize {
+        1
+    }
+
+
+    fn to_element(&self, args: &DirectiveArgs) -> Option<SynElement> {
+        let [path, alt, style, caption] = [0, 1, 2, 3].map(|i| args.positional[i].clone());
+        Some(SynElement::Figure { path, alt, style, caption, credit: args.positional.get(4).cloned() })
+    }
+
+
+    fn render(&self, args: &DirectiveArgs, options: &RenderOptions) -> String {
+        self.to_element(args).map(|element| element.generate_tag_with(options)).unwrap_or_default()
+    }
+}
+
+
+impl Directive for MediaDirective {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+
+    fn positional(&self) -> &[&str] {
+        &["path", "fallback"]
+    }
+
+
+    fn optional(&self) -> usize {
+        1
+    }
+
+
+    fn named(&self) -> &[&str] {
+        Media::KEYS
+    }
+
+
+    fn validate(&self, args: &DirectiveArgs) -> Result<(), String> {
+        let named = |key: &'static str| args.named.iter().filter(move |(k, _)| k == key).map(|(_, value)| value.as_str());
+        if args.get(0).into_iter().chain(named("source")).any(|path| path.trim().is_empty()) {
+            return Err("missing source path".into());
+        }
+        match args.get_named("poster") {
+            Some(_) if self.0 == MediaKind::Audio => return Err("audio can't have a poster".into()),
+            Some(poster) if poster.trim().is_empty() => return Err("missing poster path".into()),
+            _ => {},
+        }
+        for track in named("track") {
+            Track::parse(track).validate()?;
+        }
+        for key in ["autoplay", "loop", "muted"] {
+            if let Some(value) = named(key).find(|value| !matches!(*value, "true" | "false")) {
+                return Err(format!("{key} should be true or false, not '{value}'"));
+            }
+        }
+        Ok(())
+    }
+
+
+    fn to_element(&self, args: &DirectiveArgs) -> Option<SynElement> {
+        Some(SynElement::Media(Media::from_args(self.0, args)))
+    }
+
+
+    fn render(&self, args: &DirectiveArgs, options: &RenderOptions) -> String {
+        self.to_element(args).map(|element| element.generate_tag_with(options)).unwrap_or_default()
+    }
+}
+
+
+impl Directive for EmbedDirective {
+    fn name(&self) -> &str {
+        "embed"
+    }
+
+
+    fn positional(&self) -> &[&str] {
+        &["provider", "id", "thumbnail", "title"]
+    }
+
+
+    fn optional(&self) -> usize {
+        2
+    }
+
+
+    /// IDs go into the provider's URLs as they are, so they're kept to characters that can't
+    /// break out of a path.
+    fn validate(&self, args: &DirectiveArgs) -> Result<(), String> {
+        let (provider, id) = (&args.positional[0], &args.positional[1]);
+        if !is_identifier(provider) {
+            return Err(format!("'{provider}' isn't a provider name"));
+        }
+        match id.as_str() {
+            "" => Err("missing ID".into()),
+            id if !id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | '@')) => Err(format!("'{id}' isn't a valid ID")),
+            _ => Ok(()),
+        }
+    }
+
+
+    fn to_element(&self, args: &DirectiveArgs) -> Option<SynElement> {
+        let [provider, id] = [0, 1].map(|i| args.positional[i].clone());
+        let [thumbnail, title] = [2, 3].map(|i| args.get(i).unwrap_or_default().to_string());
+        Some(SynElement::Embed { provider, id, thumbnail, title })
+    }
+
+
+    fn render(&self, args: &DirectiveArgs, options: &RenderOptions) -> String {
+        self.to_element(args).map(|element| element.generate_tag_with(options)).unwrap_or_default()
+    }
+}
+
+
+/// Splits a directive line into its name and arguments, if it is one.
+pub(crate) fn split_directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim().strip_prefix('.')?;
+    let (name, args) = rest.split_once(' ').unwrap_or((rest, ""));
+    is_identifier(name).then_some((name, args))
+}
+
+
+/// Splits positional arguments on the `|`s that aren't escaped with a backslash, keeping the
+/// escapes.
+pub(crate) fn split_sections(source: &str) -> Vec<&str> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    for (i, c) in source.char_indices() {
+        if c == '|' && !source[..i].ends_with('\\') {
+            sections.push(&source[start..i]);
+            start = i + 1;
+        }
+    }
+    sections.push(&source[start..]);
+    sections
+}
+
+
+pub(crate) fn unescape_section(section: &str) -> String {
+    section.replace("\\|", "|")
+}
+
+
+pub(crate) fn escape_section(section: &str) -> String {
+    section.replace('|', "\\|")
+}
+
+
+/// Reads the `key=value` word at the end of a directive's arguments, if there's one with a key in
+/// `keys`, escaped or not.
+fn last_named_word<'a>(source: &'a str, keys: &[&str]) -> Option<NamedWord<'a>> {
+    // Whether the `"` after `text` is escaped by an odd number of backslashes.
+    let is_escaped = |text: &str| (text.len() - text.trim_end_matches('\\').len()) % 2 == 1;
+    let (head, value) = match source.strip_suffix('"').filter(|rest| !is_escaped(rest)) {
+        Some(rest) => {
+            let (open, _) = rest.char_indices().rev().find(|(i, c)| *c == '"' && !is_escaped(&rest[..*i]))?;
+            (rest[..open].strip_suffix('=')?, unquote(&rest[open + 1..]))
+        },
+        None => {
+            let word_start = source.rfind(' ').map_or(0, |i| i + 1);
+            let (key, value) = source[word_start..].split_once('=').filter(|(_, value)| !value.contains('"'))?;
+            (&source[..word_start + key.len()], value.to_string())
+        },
+    };
+    let start = head.rfind(' ').map_or(0, |i| i + 1);
+    let key = head[start..].trim_start_matches('\\');
+    keys.contains(&key).then(|| NamedWord { start, escapes: head.len() - start - key.len(), key, value })
+}
+
+
+/// Reads a quoted value, without its quotes, undoing its `\"` and `\\` escapes.
+fn unquote(value: &str) -> String {
+    let mut unquoted = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('"' | '\\')) => unquoted.extend(chars.next()),
+            (c, _) => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+
+pub(crate) fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase()) && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, SynFile, tests::post};
+    use std::str::FromStr;
+
+    /// `.badge label color=.. title=..`, rendered as a span.
+    struct Badge;
+
+    impl Directive for Badge {
+        fn name(&self) -> &str {
+            "badge"
+        }
+
+        fn positional(&self) -> &[&str] {
+            &["label"]
+        }
+
+        fn named(&self) -> &[&str] {
+            &["color", "title"]
+        }
+
+        fn validate(&self, args: &DirectiveArgs) -> Result<(), String> {
+            match args.get_named("color") {
+                Some(color) if !color.chars().all(|c| c.is_ascii_alphanumeric()) => Err(format!("'{color}' isn't a color name")),
+                _ => Ok(()),
+            }
+        }
+
+        fn render(&self, args: &DirectiveArgs, _options: &RenderOptions) -> String {
+            format!("<span class='badge {}'>{}</span>", args.get_named("color").unwrap_or("grey"), crate::escape_html(&args.positional[0]))
+        }
+    }
+
+    fn options() -> ParseOptions {
+        let mut options = ParseOptions::default();
+        options.directives.register(Badge);
+        options
+    }
+
+    fn parse(body: &str, options: &ParseOptions) -> Result<SynFile, SynError> {
+        SynFile::from_str_with(&post(body), options).map(|(file, _)| file)
+    }
+
+    #[test]
+    fn custom_directives() {
+        let file = parse(".badge <new> color=red title=\"Fresh \\\"out\\\" today\"\n\n.badge a \\color=blue\n\n.unknown x|y\n", &options()).unwrap();
+        let [SynElement::Custom(badge), SynElement::Custom(escaped), SynElement::Text(_)] = file.get_elements().as_slice() else {
+            panic!("{:?}", file.get_elements());
+        };
+        assert_eq!(badge.get_name(), "badge");
+        assert_eq!(badge.get_args(), &DirectiveArgs {
+            positional: vec!["<new>".into()],
+            named: vec![("color".into(), "red".into()), ("title".into(), "Fresh \"out\" today".into())],
+        });
+        assert_eq!(escaped.get_args().get(0), Some("a color=blue"));
+        assert_eq!(file.generate_html(), "<span class='badge red'>&lt;new&gt;</span>\n<span class='badge grey'>a color=blue</span>\n<p>.unknown x|y</p>");
+
+        assert_eq!(badge.generate_line(), ".badge <new> color=red title=\"Fresh \\\"out\\\" today\"");
+        assert_eq!(escaped.generate_line(), ".badge a \\color=blue");
+        let reloaded = SynFile::from_str_with(&file.to_string(), &options()).unwrap().0;
+        assert_eq!(reloaded, file);
+
+        // Without the registration, the lines are just text.
+        let file = parse(".badge new color=red\n", &ParseOptions::default()).unwrap();
+        assert!(matches!(file.get_elements()[0], SynElement::Text(_)));
+        assert_eq!(file.get_elements()[0].generate_line(), "\\.badge new color=red");
+    }
+
+    #[test]
+    fn named_arguments() {
+        let lines = [
+            (".badge a\\|b title=", vec!["a|b"], vec![("title", "")]),
+            (".badge \\title=x", vec!["title=x"], vec![]),
+            (".badge x \\\\title=\"y z\" color=red", vec!["x \\title=\"y z\""], vec![("color", "red")]),
+            (".badge say \"hi\" title=C:\\dir\\", vec!["say \"hi\""], vec![("title", "C:\\dir\\")]),
+            (".badge a  title=\"\\\"\\\\ \\\\n\"", vec!["a "], vec![("title", "\"\\ \\n")]),
+            (".badge a color=\"b", vec!["a color=\"b"], vec![]),
+        ];
+        for (line, positional, named) in lines {
+            let file = parse(&format!("{line}\n"), &options()).unwrap();
+            let [SynElement::Custom(badge)] = file.get_elements().as_slice() else {
+                panic!("{:?}", file.get_elements());
+            };
+            let named = named.into_iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+            assert_eq!(badge.get_args(), &DirectiveArgs { positional: positional.into_iter().map(String::from).collect(), named }, "{line}");
+            assert_eq!(badge.generate_line(), line);
+        }
+    }
+
+    #[test]
+    fn bare_directives_are_text() {
+        let file = parse(".img\n\n.figure  \n\n.badge\n\n.video\n", &options()).unwrap();
+        let lines = file.get_elements().iter().map(|element| match element {
+            SynElement::Text(content) => crate::Inline::plain_text(content),
+            element => panic!("{element:?}"),
+        }).collect::<Vec<_>>();
+        assert_eq!(lines, vec![".img", ".figure", ".badge", ".video"]);
+        assert_eq!(SynFile::from_str_with(&file.to_string(), &options()).unwrap().0, file);
+    }
+
+    #[test]
+    fn invalid_custom_directives() {
+        let err = parse(".badge a color=#f00\n", &options()).unwrap_err();
+        assert!(matches!(err, SynError::MalformedDirective { line: 6, column: 8, .. }));
+        assert_eq!(err.message(), "malformed .badge directive: '#f00' isn't a color name");
+
+        let err = parse("  .badge a|b\n", &options()).unwrap_err();
+        assert_eq!(err.to_string(), "6:10: malformed .badge directive: expected 1 sections separated by '|' (label), found 2");
+    }
+
+    #[test]
+    fn escaped_sections() {
+        let media = Media {
+            kind: MediaKind::Audio,
+            sources: vec!["a.mp3".into(), "b|c.ogg".into()],
+            poster: None,
+            tracks: vec![],
+            autoplay: false,
+            looping: false,
+            muted: false,
+            fallback: "x|y".into(),
+        };
+        let elements = vec![
+            SynElement::Image { path: "a|b.png".into(), alt: "two\nlines".into(), style: "".into() },
+            SynElement::Figure { path: "C:\\new\\a.png".into(), alt: "".into(), style: "".into(), caption: "x|y".into(), credit: None },
+            SynElement::Embed { provider: "youtube".into(), id: "abc".into(), thumbnail: "".into(), title: "a|b".into() },
+            SynElement::Media(media),
+        ];
+        let file = SynFile::new("Title".into(), vec![], "2024-01-01".into(), "Summary".into(), elements).unwrap();
+        let lines = file.get_elements().iter().map(SynElement::generate_line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![
+            ".img a\\|b.png|two lines|",
+            ".figure C:\\new\\a.png|||x\\|y",
+            ".embed youtube|abc||a\\|b",
+            ".audio a.mp3|x\\|y source=b|c.ogg",
+        ]);
+        assert_eq!(SynFile::from_str(&file.to_string()).unwrap().get_elements(), file.get_elements());
+
+        for path in ["C:\\new\\pic.png", "images\\nature.png", "\\\\server\\share"] {
+            let file = parse(&format!(".img {path}|alt|\n"), &options()).unwrap();
+            assert_eq!(file.get_elements(), &[SynElement::Image { path: path.into(), alt: "alt".into(), style: "".into() }]);
+        }
+
+        let figure = SynElement::Figure { path: "C:\\dir\\".into(), alt: "".into(), style: "".into(), caption: "x".into(), credit: None };
+        let err = SynFile::new("Title".into(), vec![], "2024-01-01".into(), "Summary".into(), vec![figure]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid element: .figure directive section 'C:\\dir\\' ends in a backslash, which would escape the `|` after it");
+    }
+}
@@ -0,0 +1,392 @@
+use crate::{escape_html, RenderOptions};
+
+
+// Types
+
+/// How code blocks are highlighted when rendering.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Highlighting {
+    /// Code is rendered as plain escaped text.
+    #[default]
+    Off,
+    /// Tokens are wrapped in `<span class='hl-..'>`, to be styled by `Theme::stylesheet`.
+    Classes,
+    /// Tokens carry the theme's declarations in a `style` attribute, for pages without a stylesheet.
+    InlineStyles,
+}
+
+/// The kinds of token the highlighter picks out. Anything else is left as plain text.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TokenKind {
+    Keyword,
+    Type,
+    String,
+    Number,
+    Comment,
+}
+
+/// CSS declarations for each kind of token, like `color: #d73a49`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Theme {
+    pub keyword: String,
+    pub type_name: String,
+    pub string: String,
+    pub number: String,
+    pub comment: String,
+}
+
+/// The lexical rules for one language, just enough to tell tokens apart.
+struct Language {
+    names: &'static [&'static str],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// `'` only quotes a single character, so it can also be used for things like Rust lifetimes.
+    char_literals: bool,
+    /// Identifiers starting with a capital letter are highlighted as types.
+    capitalized_types: bool,
+}
+
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn", "for",
+            "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+            "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64",
+        ],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        char_literals: true,
+        capitalized_types: true,
+    },
+    Language {
+        names: &["c", "h", "cpp", "c++", "hpp", "java", "cs", "csharp"],
+        keywords: &[
+            "break", "case", "catch", "class", "const", "continue", "default", "delete", "do", "else", "enum", "extends", "extern",
+            "false", "final", "for", "goto", "if", "implements", "import", "include", "define", "namespace", "new", "null",
+            "nullptr", "package", "private", "protected", "public", "return", "sizeof", "static", "struct", "switch", "template",
+            "this", "throw", "true", "try", "typedef", "union", "using", "virtual", "void", "volatile", "while",
+        ],
+        types: &["auto", "bool", "boolean", "byte", "char", "double", "float", "int", "long", "short", "signed", "string", "unsigned"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        char_literals: true,
+        capitalized_types: true,
+    },
+    Language {
+        names: &["javascript", "js", "jsx", "typescript", "ts", "tsx"],
+        keywords: &[
+            "as", "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do", "else",
+            "export", "extends", "false", "finally", "for", "from", "function", "if", "import", "in", "instanceof", "interface",
+            "let", "new", "null", "of", "return", "static", "super", "switch", "this", "throw", "true", "try", "type", "typeof",
+            "undefined", "var", "void", "while", "yield",
+        ],
+        types: &["any", "boolean", "never", "number", "object", "string", "unknown"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        char_literals: false,
+        capitalized_types: true,
+    },
+    Language {
+        names: &["python", "py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except", "False",
+            "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass",
+            "raise", "return", "self", "True", "try", "while", "with", "yield",
+        ],
+        types: &["bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+        capitalized_types: true,
+    },
+    Language {
+        names: &["go", "golang"],
+        keywords: &[
+            "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "false", "for", "func", "go",
+            "goto", "if", "import", "interface", "map", "nil", "package", "range", "return", "select", "struct", "switch", "true",
+            "type", "var",
+        ],
+        types: &[
+            "bool", "byte", "complex128", "error", "float32", "float64", "int", "int8", "int16", "int32", "int64", "rune", "string",
+            "uint", "uint8", "uint16", "uint32", "uint64", "uintptr",
+        ],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '`'],
+        char_literals: true,
+        capitalized_types: false,
+    },
+    Language {
+        names: &["toml"],
+        keywords: &["true", "false"],
+        types: &[],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+        capitalized_types: false,
+    },
+    Language {
+        names: &["json"],
+        keywords: &["true", "false", "null"],
+        types: &[],
+        line_comments: &[],
+        block_comment: None,
+        quotes: &['"'],
+        char_literals: false,
+        capitalized_types: false,
+    },
+    Language {
+        names: &["shell", "sh", "bash", "zsh"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local", "return", "then",
+            "until", "while",
+        ],
+        types: &[],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+        capitalized_types: false,
+    },
+];
+
+
+// Implementations
+
+impl TokenKind {
+    fn class(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "hl-keyword",
+            TokenKind::Type => "hl-type",
+            TokenKind::String => "hl-string",
+            TokenKind::Number => "hl-number",
+            TokenKind::Comment => "hl-comment",
+        }
+    }
+}
+
+
+impl Theme {
+    pub fn declarations(&self, kind: TokenKind) -> &str {
+        match kind {
+            TokenKind::Keyword => &self.keyword,
+            TokenKind::Type => &self.type_name,
+            TokenKind::String => &self.string,
+            TokenKind::Number => &self.number,
+            TokenKind::Comment => &self.comment,
+        }
+    }
+
+
+    /// The CSS rules matching the classes emitted with `Highlighting::Classes`.
+    pub fn stylesheet(&self) -> String {
+        let kinds = [TokenKind::Keyword, TokenKind::Type, TokenKind::String, TokenKind::Number, TokenKind::Comment];
+        kinds.iter().map(|kind| format!(".{} {{ {}; }}\n", kind.class(), self.declarations(*kind))).collect()
+    }
+}
+
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            keyword: "color: #d73a49".into(),
+            type_name: "color: #6f42c1".into(),
+            string: "color: #032f62".into(),
+            number: "color: #005cc5".into(),
+            comment: "color: #6a737d; font-style: italic".into(),
+        }
+    }
+}
+
+
+impl Language {
+    fn find(name: &str) -> Option<&'static Language> {
+        let name = name.to_lowercase();
+        LANGUAGES.iter().find(|language| language.names.contains(&name.as_str()))
+    }
+
+
+    /// Splits source code into runs of plain text and highlighted tokens.
+    fn tokenize(&self, source: &str) -> Vec<(Option<TokenKind>, String)> {
+        let chars = source.chars().collect::<Vec<_>>();
+        let starts_with = |pos: usize, s: &str| s.chars().enumerate().all(|(i, c)| chars.get(pos + i) == Some(&c));
+        let mut tokens: Vec<(Option<TokenKind>, String)> = Vec::new();
+        let mut pos = 0;
+
+        while pos < chars.len() {
+            let c = chars[pos];
+            let (kind, end) = if let Some(marker) = self.line_comments.iter().find(|marker| starts_with(pos, marker)) {
+                let end = chars[pos..].iter().position(|c| *c == '\n').map_or(chars.len(), |end| pos + end);
+                (Some(TokenKind::Comment), end.max(pos + marker.len()))
+            } else if let Some((open, close)) = self.block_comment.filter(|(open, _)| starts_with(pos, open)) {
+                let body = pos + open.chars().count();
+                let end = (body..chars.len()).find(|i| starts_with(*i, close)).map_or(chars.len(), |end| end + close.chars().count());
+                (Some(TokenKind::Comment), end)
+            } else if self.quotes.contains(&c) {
+                (Some(TokenKind::String), Self::string_end(&chars, pos))
+            } else if c == '\'' && self.char_literals {
+                match Self::char_literal_end(&chars, pos) {
+                    Some(end) => (Some(TokenKind::String), end),
+                    None => (None, pos + 1),
+                }
+            } else if c.is_ascii_digit() {
+                let mut end = pos + 1;
+                while chars.get(end).is_some_and(|c| c.is_alphanumeric() || *c == '_' || (*c == '.' && chars.get(end + 1).is_some_and(char::is_ascii_digit))) {
+                    end += 1;
+                }
+                (Some(TokenKind::Number), end)
+            } else if c.is_alphabetic() || c == '_' {
+                let end = (pos..chars.len()).find(|i| !(chars[*i].is_alphanumeric() || chars[*i] == '_')).unwrap_or(chars.len());
+                let word = chars[pos..end].iter().collect::<String>();
+                let kind = if self.keywords.contains(&word.as_str()) {
+                    Some(TokenKind::Keyword)
+                } else if self.types.contains(&word.as_str()) || (self.capitalized_types && c.is_uppercase()) {
+                    Some(TokenKind::Type)
+                } else {
+                    None
+                };
+                (kind, end)
+            } else {
+                (None, pos + 1)
+            };
+
+            let text = chars[pos..end].iter().collect::<String>();
+            match tokens.last_mut() {
+                Some((None, last)) if kind.is_none() => last.push_str(&text),
+                _ => tokens.push((kind, text)),
+            }
+            pos = end;
+        }
+        tokens
+    }
+
+
+    /// Finds the end of a string opened at `pos`. Strings end at the line, unless they were
+    /// opened with three quotes, in which case they run until the next three.
+    fn string_end(chars: &[char], pos: usize) -> usize {
+        let quote = chars[pos];
+        let triple = chars.get(pos..pos + 3).is_some_and(|run| run.iter().all(|c| *c == quote));
+        let mut end = match triple {
+            true => pos + 3,
+            false => pos + 1,
+        };
+        while let Some(&c) = chars.get(end) {
+            end += 1;
+            match c {
+                '\\' => end += 1,
+                '\n' if !triple && quote != '`' => return end - 1,
+                c if c == quote && (!triple || chars.get(end..end + 2).is_some_and(|run| run.iter().all(|c| *c == quote))) => {
+                    return if triple { end + 2 } else { end };
+                },
+                _ => {},
+            }
+        }
+        chars.len()
+    }
+
+
+    /// Finds the end of a character literal like `'a'` or `'\n'` opened at `pos`, if it is one.
+    fn char_literal_end(chars: &[char], pos: usize) -> Option<usize> {
+        match chars.get(pos + 1) {
+            Some('\\') => {
+                let rest = chars.get(pos + 3..).unwrap_or_default();
+                let end = rest.iter().take(10).position(|c| *c == '\'' || *c == '\n')?;
+                (rest[end] == '\'').then_some(pos + 4 + end)
+            },
+            Some(_) if chars.get(pos + 2) == Some(&'\'') => Some(pos + 3),
+            _ => None,
+        }
+    }
+}
+
+
+/// Renders the contents of a code block as HTML, highlighted if the language is one we know.
+pub(crate) fn highlight(lang: &str, source: &str, options: &RenderOptions) -> String {
+    let language = match (options.highlighting, Language::find(lang)) {
+        (Highlighting::Off, _) | (_, None) => return escape_html(source),
+        (_, Some(language)) => language,
+    };
+
+    let mut html = String::new();
+    for (kind, text) in language.tokenize(source) {
+        match kind {
+            None => html.push_str(&escape_html(&text)),
+            Some(kind) => {
+                let attribute = match options.highlighting {
+                    Highlighting::InlineStyles => format!("style='{}'", escape_html(options.theme.declarations(kind))),
+                    _ => format!("class='{}'", kind.class()),
+                };
+                html.push_str(&format!("<span {attribute}>{}</span>", escape_html(&text)));
+            },
+        }
+    }
+    html
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(highlighting: Highlighting) -> RenderOptions {
+        RenderOptions { highlighting, ..RenderOptions::default() }
+    }
+
+    #[test]
+    fn highlight_rust() {
+        let source = "fn f<'a>(x: &'a str) -> Vec<u8> { // <done>\n    let c = '\\n'; \"a\\\"b\".len() + 0x1F }";
+        assert_eq!(highlight("rust", source, &options(Highlighting::Classes)), concat!(
+            "<span class='hl-keyword'>fn</span> f&lt;&#39;a&gt;(x: &amp;&#39;a <span class='hl-type'>str</span>) -&gt; ",
+            "<span class='hl-type'>Vec</span>&lt;<span class='hl-type'>u8</span>&gt; { <span class='hl-comment'>// &lt;done&gt;</span>\n",
+            "    <span class='hl-keyword'>let</span> c = <span class='hl-string'>&#39;\\n&#39;</span>; ",
+            "<span class='hl-string'>&quot;a\\&quot;b&quot;</span>.len() + <span class='hl-number'>0x1F</span> }",
+        ));
+    }
+
+    #[test]
+    fn highlight_strings_and_comments() {
+        let source = "s = \"\"\"one\n\"two\"\"\"  # note\nx = 'unclosed\ny = 1.5";
+        assert_eq!(highlight("Python", source, &options(Highlighting::Classes)), concat!(
+            "s = <span class='hl-string'>&quot;&quot;&quot;one\n&quot;two&quot;&quot;&quot;</span>  <span class='hl-comment'># note</span>\n",
+            "x = <span class='hl-string'>&#39;unclosed</span>\ny = <span class='hl-number'>1.5</span>",
+        ));
+        assert_eq!(highlight("c", "/* a\nb */x", &options(Highlighting::Classes)), "<span class='hl-comment'>/* a\nb */</span>x");
+    }
+
+    #[test]
+    fn highlight_modes() {
+        assert_eq!(highlight("rust", "let <x>", &options(Highlighting::Off)), "let &lt;x&gt;");
+        assert_eq!(highlight("brainfuck", "let <x>", &options(Highlighting::Classes)), "let &lt;x&gt;");
+        assert_eq!(highlight("js", "let", &options(Highlighting::InlineStyles)), "<span style='color: #d73a49'>let</span>");
+
+        let stylesheet = Theme::default().stylesheet();
+        assert!(stylesheet.starts_with(".hl-keyword { color: #d73a49; }\n"));
+        assert!(stylesheet.contains(".hl-comment { color: #6a737d; font-style: italic; }\n"));
+    }
+
+    #[test]
+    fn tokens_cover_the_source() {
+        // Every short string of tricky characters, in every language.
+        const CHARS: &[char] = &['\'', '"', '\\', '\n', '/', '*', '#', '`', 'a', '1', '.'];
+        for n in 0..CHARS.len().pow(4) {
+            let source = (0..4).map(|i| CHARS[n / CHARS.len().pow(i) % CHARS.len()]).collect::<String>();
+            for language in LANGUAGES {
+                let tokens = language.tokenize(&source);
+                assert_eq!(tokens.iter().map(|(_, text)| text.as_str()).collect::<String>(), source);
+            }
+        }
+    }
+}
@@ -0,0 +1,647 @@
+use std::collections::HashMap;
+
+use crate::{escape_html, math, sanitize_url, RenderContext, RenderOptions};
+
+
+// Types
+
+/// A piece of inline content inside a paragraph or heading.
+///
+/// In source form, `_emphasis_`, `**strong**`, `` `code` `` and `[link text](href)` work as
+/// they do in Markdown, `[^id]` refers to a footnote, `$x^2$` is LaTeX math, and a backslash at
+/// the end of a line is a hard line break. A backslash before any ASCII punctuation character or
+/// a space makes it literal, so `snake\_case` keeps its underscore. HTML tags are text, markup
+/// characters and all, so `<a href='/a_b_c'>` keeps its underscores for `trusted_html`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Inline {
+    Text(String),
+    Emphasis(Vec<Inline>),
+    Strong(Vec<Inline>),
+    Code(String),
+    Link{href: String, content: Vec<Inline>},
+    LineBreak,
+    /// A reference to the footnote defined with the same ID. Footnotes are numbered in the order
+    /// they're first referenced.
+    FootnoteRef(String),
+    /// LaTeX math, rendered as MathML. Like in Pandoc, the math can't start or end with whitespace,
+    /// and the closing `$` can't be followed by a digit, so `$5 or $10` stays text.
+    Math(String),
+}
+
+/// The delimiter that ends the span currently being parsed.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+enum Closer {
+    Emphasis,
+    Strong,
+    Link,
+}
+
+/// How deeply spans may nest. Delimiters any deeper are literal text, so that nesting can't
+/// overflow the stack.
+const MAX_DEPTH: usize = 32;
+
+struct InlineParser {
+    chars: Vec<char>,
+    pos: usize,
+    in_link: bool,
+    /// How many spans we're inside.
+    depth: usize,
+    /// Earliest position an opener of each kind failed to find its closer from. Any later opener
+    /// of the same kind can't succeed either, which keeps unbalanced input from going exponential.
+    failed: HashMap<Closer, usize>,
+}
+
+
+// Implementations
+
+impl Inline {
+    /// Parses inline markup. Newlines are soft line breaks, and read as spaces.
+    pub fn parse(source: &str) -> Vec<Inline> {
+        let mut parser = InlineParser { chars: source.chars().collect(), pos: 0, in_link: false, depth: 0, failed: HashMap::new() };
+        parser.parse_until(None).0
+    }
+
+
+    /// The source form of a run of inlines. The result may still need its line starts escaped
+    /// before it can be written out as a block.
+    pub fn generate_line(content: &[Inline]) -> String {
+        let mut line = String::new();
+        for inline in content {
+            match inline {
+                Inline::Text(text) => {
+                    for c in text.chars() {
+                        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '$') {
+                            line.push('\\');
+                        }
+                        line.push(c);
+                    }
+                },
+                Inline::Emphasis(content) => line.push_str(&format!("_{}_", Self::generate_line(content))),
+                Inline::Strong(content) => line.push_str(&format!("**{}**", Self::generate_line(content))),
+                Inline::Code(code) => {
+                    let longest_run = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+                    let fence = "`".repeat(longest_run + 1);
+                    let stripped = code.starts_with(' ') && code.ends_with(' ') && code.trim() != "";
+                    let pad = if code.starts_with('`') || code.ends_with('`') || stripped { " " } else { "" };
+                    line.push_str(&format!("{fence}{pad}{code}{pad}{fence}"));
+                },
+                Inline::Link { href, content } => {
+                    let href = href.replace('\\', "\\\\").replace(')', "\\)");
+                    let content = Self::generate_line(content);
+                    // `[^` would start a footnote reference instead.
+                    let escape = if content.starts_with('^') { "\\" } else { "" };
+                    line.push_str(&format!("[{escape}{content}]({href})"));
+                },
+                Inline::LineBreak => line.push_str("\\\n"),
+                Inline::FootnoteRef(id) => line.push_str(&format!("[^{id}]")),
+                Inline::Math(tex) => line.push_str(&format!("${tex}$")),
+            }
+        }
+        line
+    }
+
+
+    /// The content as reading back its source form would give it: newlines become spaces, empty
+    /// text, code and math are left out, adjacent text and code merged, and whitespace is dropped
+    /// at the start and end of the line and after each line break, and around math. Emphasis
+    /// directly inside emphasis and strong text directly inside strong text are unwrapped, and so
+    /// are links inside links, since none of them can be written nested.
+    pub(crate) fn normalize(content: Vec<Inline>) -> Vec<Inline> {
+        let mut content = Self::normalize_spans(content);
+        if let Some(Inline::Text(text)) = content.first_mut() {
+            *text = text.trim_start().to_string();
+        }
+        if let Some(Inline::Text(text)) = content.last_mut() {
+            *text = text.trim_end().to_string();
+        }
+        content.retain(|inline| !matches!(inline, Inline::Text(text) if text.is_empty()));
+        content
+    }
+
+
+    /// Like `normalize`, for content that's written on a single line, like a heading's, where line
+    /// breaks become spaces as well.
+    pub(crate) fn normalize_line(content: Vec<Inline>) -> Vec<Inline> {
+        Self::normalize(Self::unbreak(content))
+    }
+
+
+    fn normalize_spans(content: Vec<Inline>) -> Vec<Inline> {
+        let mut normalized: Vec<Inline> = Vec::new();
+        for inline in content {
+            let inline = match inline {
+                Inline::Text(text) => Inline::Text(text.replace('\n', " ")),
+                Inline::Code(code) => Inline::Code(code.replace('\n', " ")),
+                Inline::Math(tex) => Inline::Math(tex.replace('\n', " ").trim().to_string()),
+                Inline::Emphasis(content) => Inline::Emphasis(Self::normalize_spans(Self::unnest(content, false))),
+                Inline::Strong(content) => Inline::Strong(Self::normalize_spans(Self::unnest(content, true))),
+                Inline::Link { href, content } => Inline::Link { href: href.replace('\n', " "), content: Self::normalize_spans(Self::unlink(content)) },
+                inline => inline,
+            };
+            match (normalized.last_mut(), inline) {
+                (_, Inline::Text(text) | Inline::Code(text) | Inline::Math(text)) if text.is_empty() => {},
+                (Some(Inline::Text(last)), Inline::Text(text)) => last.push_str(&text),
+                (Some(Inline::Code(last)), Inline::Code(code)) => last.push_str(&code),
+                (_, inline) => normalized.push(inline),
+            }
+        }
+        // A line break ends the source line, and the next one is read without its indentation.
+        for i in 1..normalized.len() {
+            if let [Inline::LineBreak, Inline::Text(text)] = &mut normalized[i - 1..=i] {
+                *text = text.trim_start().to_string();
+            }
+        }
+        normalized.retain(|inline| !matches!(inline, Inline::Text(text) if text.is_empty()));
+        normalized
+    }
+
+
+    /// The content of an emphasis span, or a strong one if `strong` is set, with any spans of the
+    /// same kind directly inside it unwrapped.
+    fn unnest(content: Vec<Inline>, strong: bool) -> Vec<Inline> {
+        content.into_iter().flat_map(|inline| match (inline, strong) {
+            (Inline::Emphasis(inner), false) | (Inline::Strong(inner), true) => Self::unnest(inner, strong),
+            (inline, _) => vec![inline],
+        }).collect()
+    }
+
+
+    /// The text of a link, with any links inside it replaced by their own text.
+    fn unlink(content: Vec<Inline>) -> Vec<Inline> {
+        content.into_iter().flat_map(|inline| match inline {
+            Inline::Link { content, .. } => Self::unlink(content),
+            Inline::Emphasis(inner) => vec![Inline::Emphasis(Self::unlink(inner))],
+            Inline::Strong(inner) => vec![Inline::Strong(Self::unlink(inner))],
+            inline => vec![inline],
+        }).collect()
+    }
+
+
+    fn unbreak(content: Vec<Inline>) -> Vec<Inline> {
+        content.into_iter().map(|inline| match inline {
+            Inline::LineBreak => Inline::Text(" ".into()),
+            Inline::Emphasis(inner) => Inline::Emphasis(Self::unbreak(inner)),
+            Inline::Strong(inner) => Inline::Strong(Self::unbreak(inner)),
+            Inline::Link { href, content } => Inline::Link { href, content: Self::unbreak(content) },
+            inline => inline,
+        }).collect()
+    }
+
+
+    /// Checks that normalized content can be written out so it reads back the same, returning a
+    /// message if it can't.
+    pub(crate) fn validate(content: &[Inline]) -> Result<(), String> {
+        Self::validate_spans(content, false)
+    }
+
+
+    fn validate_spans(content: &[Inline], in_link: bool) -> Result<(), String> {
+        for (i, inline) in content.iter().enumerate() {
+            match inline {
+                Inline::Math(tex) => {
+                    if Self::parse(&format!("${tex}$")) != [inline.clone()] {
+                        return Err(format!("`{tex}` can't be written as inline math"));
+                    }
+                    if let Some(Inline::Text(text)) = content.get(i + 1).filter(|next| matches!(next, Inline::Text(text) if text.starts_with(|c: char| c.is_ascii_digit()))) {
+                        return Err(format!("inline math `{tex}` can't be followed by a digit, as in `{text}`"));
+                    }
+                    math::validate(tex).map_err(|message| format!("malformed math `{tex}`: {message}"))?;
+                },
+                Inline::FootnoteRef(id) if in_link => return Err(format!("footnote reference [^{id}] can't be inside a link")),
+                Inline::FootnoteRef(id) if id.is_empty() || !id.chars().all(is_footnote_id_char) => return Err(format!("'{id}' isn't a footnote ID")),
+                Inline::Emphasis(content) | Inline::Strong(content) => Self::validate_spans(content, in_link)?,
+                Inline::Link { content, .. } => Self::validate_spans(content, true)?,
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+
+    pub fn generate_tag(content: &[Inline], options: &RenderOptions) -> String {
+        Self::render(content, &mut RenderContext::new(options))
+    }
+
+
+    pub(crate) fn render(content: &[Inline], context: &mut RenderContext) -> String {
+        let options = context.options;
+        let mut html = String::new();
+        for inline in content {
+            match inline {
+                Inline::Text(text) => match options.trusted_html {
+                    true => html.push_str(text),
+                    false if context.in_paragraph => html.push_str(&context.gloss(text)),
+                    false => html.push_str(&escape_html(text)),
+                },
+                Inline::Emphasis(content) => html.push_str(&format!("<em>{}</em>", Self::render(content, context))),
+                Inline::Strong(content) => html.push_str(&format!("<strong>{}</strong>", Self::render(content, context))),
+                Inline::Code(code) => html.push_str(&format!("<code>{}</code>", escape_html(code))),
+                Inline::Link { href, content } => {
+                    let href = match options.trusted_html {
+                        true => href.clone(),
+                        false => sanitize_url(href),
+                    };
+                    // Terms aren't glossed inside links, which would nest their titles.
+                    let in_paragraph = std::mem::replace(&mut context.in_paragraph, false);
+                    let content = Self::render(content, context);
+                    context.in_paragraph = in_paragraph;
+                    html.push_str(&format!("<a href='{}'>{content}</a>", escape_html(&href)));
+                },
+                Inline::LineBreak => html.push_str("<br>"),
+                Inline::FootnoteRef(id) => html.push_str(&context.footnote_ref(id)),
+                Inline::Math(tex) => html.push_str(&math::render(tex, false)),
+            }
+        }
+        html
+    }
+
+
+    /// Calls `visit` with each inline in the content, in order, including the ones nested in others.
+    pub(crate) fn visit<'a>(content: &'a [Inline], visit: &mut impl FnMut(&'a Inline)) {
+        for inline in content {
+            visit(inline);
+            if let Inline::Emphasis(content) | Inline::Strong(content) | Inline::Link { content, .. } = inline {
+                Self::visit(content, visit);
+            }
+        }
+    }
+
+
+    /// The text of the content with all markup stripped, for slugs and other plain-text uses.
+    pub fn plain_text(content: &[Inline]) -> String {
+        let mut text = String::new();
+        for inline in content {
+            match inline {
+                Inline::Text(t) | Inline::Code(t) | Inline::Math(t) => text.push_str(t),
+                Inline::Emphasis(content) | Inline::Strong(content) | Inline::Link { content, .. } => text.push_str(&Self::plain_text(content)),
+                Inline::LineBreak => text.push(' '),
+                Inline::FootnoteRef(_) => {},
+            }
+        }
+        text
+    }
+}
+
+
+impl InlineParser {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+
+    /// Parses until the given closer, returning the content and whether the closer was found.
+    fn parse_until(&mut self, closer: Option<Closer>) -> (Vec<Inline>, bool) {
+        let mut content = Vec::new();
+        let mut text = String::new();
+        let flush = |text: &mut String, content: &mut Vec<Inline>| {
+            if !text.is_empty() {
+                content.push(Inline::Text(std::mem::take(text)));
+            }
+        };
+
+        while let Some(c) = self.peek(0) {
+            match c {
+                '\\' => match self.peek(1) {
+                    None | Some('\n') => {
+                        flush(&mut text, &mut content);
+                        content.push(Inline::LineBreak);
+                        self.pos += 2;
+                    },
+                    Some(next) if next.is_ascii_punctuation() || next == ' ' => {
+                        text.push(next);
+                        self.pos += 2;
+                    },
+                    Some(_) => {
+                        text.push('\\');
+                        self.pos += 1;
+                    },
+                },
+                '\n' => {
+                    text.push(' ');
+                    self.pos += 1;
+                },
+                '`' => match self.parse_code() {
+                    Some(code) => {
+                        flush(&mut text, &mut content);
+                        content.push(Inline::Code(code));
+                    },
+                    None => {
+                        while self.peek(0) == Some('`') {
+                            text.push('`');
+                            self.pos += 1;
+                        }
+                    },
+                },
+                '_' if closer == Some(Closer::Emphasis) => {
+                    self.pos += 1;
+                    flush(&mut text, &mut content);
+                    return (content, true);
+                },
+                '*' if self.peek(1) == Some('*') && closer == Some(Closer::Strong) => {
+                    self.pos += 2;
+                    flush(&mut text, &mut content);
+                    return (content, true);
+                },
+                ']' if closer == Some(Closer::Link) => {
+                    self.pos += 1;
+                    flush(&mut text, &mut content);
+                    return (content, true);
+                },
+                '_' => match self.parse_span(Closer::Emphasis, 1) {
+                    Some(inner) => {
+                        flush(&mut text, &mut content);
+                        content.push(Inline::Emphasis(inner));
+                    },
+                    None => text.push('_'),
+                },
+                '*' if self.peek(1) == Some('*') => match self.parse_span(Closer::Strong, 2) {
+                    Some(inner) => {
+                        flush(&mut text, &mut content);
+                        content.push(Inline::Strong(inner));
+                    },
+                    None => text.push_str("**"),
+                },
+                '<' => match self.parse_tag() {
+                    Some(tag) => text.push_str(&tag),
+                    None => {
+                        text.push('<');
+                        self.pos += 1;
+                    },
+                },
+                '$' => match self.parse_math() {
+                    Some(tex) => {
+                        flush(&mut text, &mut content);
+                        content.push(Inline::Math(tex));
+                    },
+                    None => {
+                        text.push('$');
+                        self.pos += 1;
+                    },
+                },
+                '[' if !self.in_link && self.footnote_ref().is_some() => {
+                    let id = self.footnote_ref().unwrap_or_default();
+                    self.pos += id.chars().count() + 3;
+                    flush(&mut text, &mut content);
+                    content.push(Inline::FootnoteRef(id));
+                },
+                '[' if !self.in_link => match self.parse_link() {
+                    Some(link) => {
+                        flush(&mut text, &mut content);
+                        content.push(link);
+                    },
+                    None => text.push('['),
+                },
+                c => {
+                    text.push(c);
+                    self.pos += 1;
+                },
+            }
+        }
+
+        flush(&mut text, &mut content);
+        (content, false)
+    }
+
+
+    /// Tries to parse a delimited span starting at the current position. On failure, the position
+    /// is moved past the opening delimiter so it can be treated as literal text.
+    fn parse_span(&mut self, closer: Closer, opener_len: usize) -> Option<Vec<Inline>> {
+        let start = self.pos;
+        self.pos += opener_len;
+        if self.depth == MAX_DEPTH || self.failed.get(&closer).is_some_and(|failed| *failed <= start) {
+            return None;
+        }
+
+        self.depth += 1;
+        let parsed = self.parse_until(Some(closer));
+        self.depth -= 1;
+        match parsed {
+            (content, true) => Some(content),
+            (_, false) => {
+                self.failed.insert(closer, start);
+                self.pos = start + opener_len;
+                None
+            },
+        }
+    }
+
+
+    fn parse_link(&mut self) -> Option<Inline> {
+        let start = self.pos;
+        self.in_link = true;
+        let content = self.parse_span(Closer::Link, 1);
+        self.in_link = false;
+
+        let href = content.as_ref().and_then(|_| self.parse_href());
+        match (content, href) {
+            (Some(content), Some(href)) => Some(Inline::Link { href, content }),
+            _ => {
+                self.pos = start + 1;
+                None
+            },
+        }
+    }
+
+
+    /// Parses a parenthesized link target, where `\)` and `\\` are escapes.
+    fn parse_href(&mut self) -> Option<String> {
+        if self.peek(0) != Some('(') {
+            return None;
+        }
+        let mut href = String::new();
+        let mut pos = self.pos + 1;
+        while let Some(&c) = self.chars.get(pos) {
+            match c {
+                ')' => {
+                    self.pos = pos + 1;
+                    return Some(href);
+                },
+                '\\' if matches!(self.chars.get(pos + 1), Some(')' | '\\')) => {
+                    href.push(self.chars[pos + 1]);
+                    pos += 2;
+                },
+                c => {
+                    href.push(c);
+                    pos += 1;
+                },
+            }
+        }
+        None
+    }
+
+
+    /// The ID of the `[^id]` footnote reference at the current position, if there is one.
+    fn footnote_ref(&self) -> Option<String> {
+        if self.peek(0) != Some('[') || self.peek(1) != Some('^') {
+            return None;
+        }
+        let id = self.chars[self.pos + 2..].iter().take_while(|c| is_footnote_id_char(**c)).collect::<String>();
+        match self.peek(id.chars().count() + 2) {
+            Some(']') if !id.is_empty() => Some(id),
+            _ => None,
+        }
+    }
+
+
+    /// Reads an HTML tag like `<a href='x'>` or `</a>` as it is, apart from escapes. Leaves the
+    /// position untouched if it isn't one.
+    fn parse_tag(&mut self) -> Option<String> {
+        let mut pos = self.pos + 1 + usize::from(self.peek(1) == Some('/'));
+        if !self.chars.get(pos).is_some_and(char::is_ascii_alphabetic) {
+            return None;
+        }
+        while self.chars.get(pos).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '-') {
+            pos += 1;
+        }
+        if !self.chars.get(pos).is_some_and(|c| c.is_whitespace() || matches!(c, '/' | '>')) {
+            return None;
+        }
+
+        let mut tag = self.chars[self.pos..pos].iter().collect::<String>();
+        while let Some(&c) = self.chars.get(pos) {
+            match c {
+                '>' => {
+                    tag.push('>');
+                    self.pos = pos + 1;
+                    return Some(tag);
+                },
+                '\\' if self.chars.get(pos + 1).is_some_and(|c| c.is_ascii_punctuation() || *c == ' ') => {
+                    tag.push(self.chars[pos + 1]);
+                    pos += 2;
+                },
+                '\n' => {
+                    tag.push(' ');
+                    pos += 1;
+                },
+                c => {
+                    tag.push(c);
+                    pos += 1;
+                },
+            }
+        }
+        None
+    }
+
+
+    /// Parses `$math$`, where `\$` doesn't close the math. Leaves the position untouched if
+    /// there's no valid closing `$`.
+    fn parse_math(&mut self) -> Option<String> {
+        if self.peek(1).is_none_or(|c| c.is_whitespace() || c == '$') {
+            return None;
+        }
+        let mut pos = self.pos + 1;
+        while let Some(&c) = self.chars.get(pos) {
+            match c {
+                '\\' => pos += 2,
+                '$' if !self.chars[pos - 1].is_whitespace() && !self.chars.get(pos + 1).is_some_and(char::is_ascii_digit) => {
+                    let tex = self.chars[self.pos + 1..pos].iter().map(|c| if *c == '\n' { ' ' } else { *c }).collect();
+                    self.pos = pos + 1;
+                    return Some(tex);
+                },
+                _ => pos += 1,
+            }
+        }
+        None
+    }
+
+
+    /// Parses a code span opened by a run of backticks and closed by a run of the same length.
+    /// Leaves the position untouched if there's no closing run.
+    fn parse_code(&mut self) -> Option<String> {
+        let run = self.chars[self.pos..].iter().take_while(|c| **c == '`').count();
+        let mut pos = self.pos + run;
+        while pos < self.chars.len() {
+            let closing = self.chars[pos..].iter().take_while(|c| **c == '`').count();
+            if closing == run {
+                let code = self.chars[self.pos + run..pos].iter().map(|c| if *c == '\n' { ' ' } else { *c }).collect::<String>();
+                self.pos = pos + run;
+                // One space of padding on each side lets code start or end with a backtick.
+                let stripped = code.starts_with(' ') && code.ends_with(' ') && code.trim() != "";
+                return Some(match stripped {
+                    true => code[1..code.len() - 1].to_string(),
+                    false => code,
+                });
+            }
+            pos += closing.max(1);
+        }
+        None
+    }
+}
+
+
+pub(crate) fn is_footnote_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.into())
+    }
+
+    #[test]
+    fn parse_markup() {
+        let parsed = Inline::parse("Some _emphasis_, **strong _nested_** and `co*de` with a [link](https://example.com/a_(b\\)).");
+        assert_eq!(parsed, vec![
+            text("Some "),
+            Inline::Emphasis(vec![text("emphasis")]),
+            text(", "),
+            Inline::Strong(vec![text("strong "), Inline::Emphasis(vec![text("nested")])]),
+            text(" and "),
+            Inline::Code("co*de".into()),
+            text(" with a "),
+            Inline::Link { href: "https://example.com/a_(b)".into(), content: vec![text("link")] },
+            text("."),
+        ]);
+    }
+
+    #[test]
+    fn unbalanced_markup_is_literal() {
+        assert_eq!(Inline::parse("a_b **c [d] `e"), vec![text("a_b **c [d] `e")]);
+        assert_eq!(Inline::parse("\\_not emphasis\\_ and a\\\\b"), vec![text("_not emphasis_ and a\\b")]);
+        assert_eq!(Inline::parse("soft\nwrap\\\nhard"), vec![text("soft wrap"), Inline::LineBreak, text("hard")]);
+
+        // Spans past the depth limit are left as text.
+        fn depth(content: &[Inline]) -> usize {
+            content.iter().map(|inline| match inline {
+                Inline::Emphasis(content) | Inline::Strong(content) | Inline::Link { content, .. } => depth(content) + 1,
+                _ => 0,
+            }).max().unwrap_or(0)
+        }
+        assert_eq!(depth(&Inline::parse(&"_**".repeat(2000))), MAX_DEPTH);
+        assert_eq!(depth(&Inline::parse(&"[a".repeat(2000))), 0);
+    }
+
+    #[test]
+    fn code_spans_with_backticks() {
+        let code = Inline::Code("a `tick` b".into());
+        assert_eq!(Inline::generate_line(std::slice::from_ref(&code)), "``a `tick` b``");
+        assert_eq!(Inline::parse("``a `tick` b``"), vec![code]);
+
+        let code = Inline::Code("`".into());
+        assert_eq!(Inline::generate_line(std::slice::from_ref(&code)), "`` ` ``");
+        assert_eq!(Inline::parse("`` ` ``"), vec![code]);
+    }
+
+    #[test]
+    fn render_markup() {
+        let content = Inline::parse("**<b>** [x](javascript:alert(1\\)) `<i>`\\\nnext");
+        let html = Inline::generate_tag(&content, &RenderOptions::default());
+        assert_eq!(html, "<strong>&lt;b&gt;</strong> <a href='#'>x</a> <code>&lt;i&gt;</code><br>next");
+        assert_eq!(Inline::plain_text(&content), "<b> x <i> next");
+    }
+
+    #[test]
+    fn math_spans() {
+        assert_eq!(Inline::parse("so $e^{i\\pi} = -1$, and $a\\$b$"), vec![
+            text("so "), Inline::Math("e^{i\\pi} = -1".into()), text(", and "), Inline::Math("a\\$b".into()),
+        ]);
+        assert_eq!(Inline::parse("$5 or $10"), vec![text("$5 or $10")]);
+        assert_eq!(Inline::parse("$ x$ and $x $"), vec![text("$ x$ and $x $")]);
+        assert_eq!(Inline::parse("$$"), vec![text("$$")]);
+        assert_eq!(Inline::generate_line(&[text("$5"), Inline::Math("x".into())]), "\\$5$x$");
+    }
+}
@@ -1,226 +1,3832 @@
-use std::{fs::File, io::{BufRead, BufReader, Write}, path::Path};
+mod directive;
+mod highlight;
+mod inline;
+mod math;
+
+pub use directive::{CustomElement, Directive, DirectiveArgs, Directives};
+pub use highlight::{Highlighting, Theme, TokenKind};
+pub use inline::Inline;
+
+use directive::{escape_section, is_identifier, split_directive, split_sections, unescape_section};
+use inline::is_footnote_id_char;
+use std::{collections::{HashMap, HashSet}, error::Error, fmt, fs::File, io::{self, BufRead, BufReader, BufWriter, Write}, path::{Path, PathBuf}, str::FromStr};
 
 
 // Types
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum SynElement {
-    Text(String),
-    Heading(String),
+    Text(Vec<Inline>),
+    /// A section heading. `level` runs from 1 to 6, written as that many `#`s, optionally followed
+    /// by a space as in Markdown.
+    Heading{level: u8, content: Vec<Inline>},
     Image{path: String, alt: String, style: String},
+    /// An image with a visible caption, and optionally a credit line for the image's source.
+    Figure{path: String, alt: String, style: String, caption: String, credit: Option<String>},
     LineH,
+    /// A fenced code block. `source` is kept exactly as written, and `lang` is the rest of the
+    /// opening fence line, whose first word picks the language class.
+    Code{lang: String, source: String},
+    List(List),
+    /// A block quote, written as lines starting with `>` that hold any other elements. An optional
+    /// `-- Name|url` line straight after them credits the source.
+    Quote{children: Vec<SynElement>, attribution: Option<String>, cite: Option<String>},
+    Table(Table),
+    /// An element from a directive registered in `ParseOptions::directives`.
+    Custom(CustomElement),
+    /// A group of elements between a `:::name title` line and a `:::` line. `details` containers
+    /// render as a collapsible section with the title as its summary, and any other name as a
+    /// `<div>` with that class.
+    Container{name: String, title: Vec<Inline>, children: Vec<SynElement>},
+    /// A note, tip or warning box, written as a quote whose first line is `[!KIND]` and an
+    /// optional title. `kind` is lowercase, and looked up in `RenderOptions::callouts`.
+    Callout{kind: String, title: Vec<Inline>, children: Vec<SynElement>},
+    /// The text of a footnote, written as `[^id]: text` and referenced elsewhere as `[^id]`. Lines
+    /// straight after the first one continue it, up to a blank line.
+    Footnote{id: String, content: Vec<Inline>},
+    /// A display equation, written in LaTeX between lines holding just `$$`, and rendered as
+    /// MathML. `source` is kept exactly as written.
+    Math{source: String},
+    /// A video or audio player, from a `.video` or `.audio` line.
+    Media(Media),
+    /// Content hosted by a third party, like a video or a post, written as
+    /// `.embed provider|id|thumbnail|title`. The thumbnail and title may be left out. It renders as
+    /// a link to the content that a script can swap for the provider's player on click, so nothing
+    /// is loaded from the provider until then. `provider` is looked up in `RenderOptions::embeds`.
+    Embed{provider: String, id: String, thumbnail: String, title: String},
+    /// Terms and their definitions, written as a `; term` line followed by `: definition` lines.
+    /// Lines after either that don't start another one continue it, up to a blank line.
+    DefinitionList(Vec<Definition>),
+}
+
+/// A bulleted or numbered list. Items start with `- `, `* ` or a number like `1. `, and a `[ ]` or
+/// `[x]` right after the marker makes a task item. Items indented further than the one before
+/// them form a nested list inside it.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct List {
+    /// The number of the first item in an ordered list, or `None` for a bulleted one.
+    pub start: Option<u64>,
+    pub items: Vec<ListItem>,
+}
+
+/// A table, written as rows of cells between `|`s. The first row is the header, and the second
+/// marks each column's alignment with `---`, `:--`, `:-:` or `--:`. A `|` inside a cell is
+/// written `\|`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Table {
+    pub header: Vec<Vec<Inline>>,
+    pub alignments: Vec<Alignment>,
+    /// The body rows, each with as many cells as the header.
+    pub rows: Vec<Vec<Vec<Inline>>>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Alignment {
+    #[default]
+    Default,
+    Left,
+    Center,
+    Right,
+}
+
+/// A video or audio player, written as `.video path|fallback` or `.audio path|fallback`. Named
+/// arguments after them add more: `source=` for the same media in another format, `poster=` for a
+/// video's preview image, `track=path.vtt;lang;label` for a caption track, and `autoplay=true`,
+/// `loop=true` or `muted=true`. `source=` and `track=` can be given more than once.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Media {
+    pub kind: MediaKind,
+    /// Paths to the media in different formats, in order of preference.
+    pub sources: Vec<String>,
+    pub poster: Option<String>,
+    pub tracks: Vec<Track>,
+    pub autoplay: bool,
+    pub looping: bool,
+    pub muted: bool,
+    /// Shown instead of the player by browsers that can't play the media, along with a link to
+    /// download it.
+    pub fallback: String,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MediaKind {
+    Video,
+    Audio,
+}
+
+/// A WebVTT caption track for a video or audio player.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Track {
+    pub path: String,
+    /// The language of the captions, like `en`.
+    pub lang: String,
+    /// The name shown in the player's caption menu. Defaults to the language.
+    pub label: String,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Definition {
+    pub term: Vec<Inline>,
+    /// The definitions of the term. Without any, it shares the next term's definitions.
+    pub details: Vec<Vec<Inline>>,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ListItem {
+    /// Whether a task item is done, or `None` if this isn't a task item.
+    pub checked: Option<bool>,
+    pub content: Vec<Inline>,
+    /// Lists nested inside this item. Adjacent ones of the same kind would read back as one list.
+    pub children: Vec<List>,
+}
+
+/// One of the four fixed header lines at the top of a post.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HeaderField {
+    Title,
+    Tags,
+    Posted,
+    Summary,
+}
+
+/// Everything that can go wrong while loading or parsing a post.
+///
+/// Lines and columns are 1-based. `path` is the file being read, if any.
+#[derive(Debug)]
+pub enum SynError {
+    Io { path: Option<PathBuf>, source: io::Error },
+    InvalidUtf8 { path: Option<PathBuf>, line: usize, column: usize },
+    MissingHeader { path: Option<PathBuf>, field: HeaderField, line: usize },
+    MalformedDirective { path: Option<PathBuf>, line: usize, column: usize, directive: String, message: String },
+    MalformedTable { path: Option<PathBuf>, line: usize, column: usize, message: String },
+    MalformedContainer { path: Option<PathBuf>, line: usize, column: usize, message: String },
+    MalformedQuote { path: Option<PathBuf>, line: usize, column: usize, message: String },
+    MalformedList { path: Option<PathBuf>, line: usize, column: usize, message: String },
+    /// A block opened on `line` was still open at the end of the file.
+    UnclosedBlock { path: Option<PathBuf>, line: usize, column: usize, delimiter: String },
+    /// A footnote that's referenced but never defined, defined but never referenced, or defined
+    /// more than once. `line` is where the reference or definition is.
+    InvalidFootnote { path: Option<PathBuf>, line: usize, column: usize, id: String, message: String },
+    /// Math using LaTeX we can't convert. `line` is the start of the block holding it.
+    MalformedMath { path: Option<PathBuf>, line: usize, column: usize, message: String },
+    /// A `key: value` line after the summary that can't be read, like one with an unclosed quote
+    /// or a key that's already been set.
+    MalformedMetadata { path: Option<PathBuf>, line: usize, column: usize, message: String },
+    /// An element given to `SynFile::new` that can't be saved in a form that loads back the same,
+    /// like a directive section ending in a backslash that would escape the `|` after it, math we
+    /// can't convert or a footnote that's never defined.
+    InvalidElement { message: String },
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A region of the source file. Lines and columns are 1-based, and the end is exclusive.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// A problem found while parsing in recovering mode.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+    /// Replacement text for the spanned region that would fix the problem, if there's an obvious one.
+    pub suggestion: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    /// Keep going after errors, collecting them as diagnostics instead of failing on the first one.
+    pub recover: bool,
+    /// The directives recognized in `.name` lines. Lines naming any other directive are read as
+    /// paragraph text.
+    pub directives: Directives,
+    /// How deeply `:::` containers may be nested inside each other, and likewise `>` quotes,
+    /// callouts and lists. Defaults to 16.
+    pub max_container_depth: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// Emit text and headings as raw HTML, and skip URL and style sanitization. Only use this for
+    /// posts from trusted authors; attribute values are still escaped either way.
+    pub trusted_html: bool,
+    /// The HTML heading level that level 1 headings render as, so post headings can sit under the
+    /// page's own `<h1>`. Deeper levels follow on from it, capped at `<h6>`. Defaults to 2.
+    pub heading_base_level: u8,
+    /// Whether to highlight code blocks in languages we know, and how.
+    pub highlighting: Highlighting,
+    /// The styles used with `Highlighting::InlineStyles`. With `Highlighting::Classes`, put
+    /// `theme.stylesheet()` on the page instead.
+    pub theme: Theme,
+    /// How each kind of callout is presented, by lowercase kind. Starts out with `note`, `tip`,
+    /// `important`, `warning` and `caution`; other kinds fall back to their name as the label.
+    pub callouts: HashMap<String, CalloutKind>,
+    /// Render footnotes as sidenotes next to where they're first referenced, for styling into the
+    /// margin, instead of as a numbered list at the end of the post.
+    pub sidenotes: bool,
+    /// Where embeds link to and load from, by provider name. Starts out with `youtube`, `vimeo`
+    /// and `mastodon`, whose IDs are like `instance.social/@user/123`.
+    pub embeds: HashMap<String, EmbedProvider>,
+    /// Definitions of terms, by term. The first time a term appears in a paragraph, it's wrapped in
+    /// a `<dfn>` with the definition as its title, or an `<abbr>` if it's written in capitals, like
+    /// `HTML`. Terms defined in a post's definition lists are added for that post. Not used with
+    /// `trusted_html`, since the text is HTML then.
+    pub glossary: HashMap<String, String>,
 }
 
+#[derive(Clone, Debug)]
+pub struct EmbedProvider {
+    /// The content's page on the provider's site, with `{id}` standing for the embed's ID.
+    pub link: String,
+    /// The provider's embeddable player or widget, with `{id}` standing for the ID. It goes in a
+    /// `data-embed-src` attribute, for a script to load into an iframe once the visitor asks.
+    pub frame: String,
+    /// The text of the link, like "Watch on YouTube".
+    pub action: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct CalloutKind {
+    /// The heading shown when the callout has no title of its own.
+    pub label: String,
+    /// Markup put before the title, such as an emoji or an inline SVG. It's inserted as is.
+    pub icon: String,
+    /// The ARIA role of the callout's box.
+    pub role: String,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct SynFile {
     title: String,
     tags: Vec<String>,
     posted: String,
     summary: String,
+    metadata: Metadata,
     elements: Vec<SynElement>,
 }
 
+/// Optional header fields beyond the fixed four, like an author or a draft flag. They're written
+/// as `key: value` lines between two `---` lines, the first straight after the summary, and keep
+/// the order they're written in. Keys are lowercase identifiers, like directive names.
+///
+/// Files from before there was metadata have a blank line after the summary, so they read the
+/// same. So do ones starting with a `---` rule, unless every line up to the next `---` is a
+/// `key: value` line. Saving always writes the blank line when there's no metadata.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Metadata {
+    entries: Vec<(String, MetadataValue)>,
+}
+
+/// A metadata value. `true` and `false` read as booleans and whole numbers as integers; anything
+/// else is text. Text that would read as something else is written in double quotes, with `\"`,
+/// `\\`, `\n` and `\r` escapes.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum MetadataValue {
+    Text(String),
+    Bool(bool),
+    Integer(i64),
+}
+
+
+/// The start of a line that begins a list item.
+struct ListMarker<'a> {
+    indent: usize,
+    number: Option<u64>,
+    checked: Option<bool>,
+    text: &'a str,
+}
+
+
+/// Reads a file line by line, keeping track of where we are for error reporting.
+struct LineReader<R> {
+    reader: R,
+    line: usize,
+    /// Lines put back with `unread`, last first, to be read again before the rest.
+    unread: Vec<String>,
+}
+
+/// State carried across a whole document while rendering it.
+struct RenderContext<'a> {
+    options: &'a RenderOptions,
+    /// Heading IDs handed out so far, so repeated headings get distinct anchors.
+    slugs: HashSet<String>,
+    /// The content of each footnote defined in the document.
+    footnotes: HashMap<&'a str, &'a [Inline]>,
+    /// Footnote IDs in order of first reference, which gives their numbers.
+    footnote_order: Vec<String>,
+    /// How many times each footnote has been referenced so far.
+    footnote_refs: HashMap<String, usize>,
+    /// Glossary terms and their definitions, longest terms first so they win over terms they
+    /// contain.
+    glossary: Vec<(String, String)>,
+    /// Glossary terms that have been wrapped already.
+    glossed: HashSet<String>,
+    /// Whether the text being rendered is paragraph text, where glossary terms are looked for.
+    in_paragraph: bool,
+}
+
+/// Collects the problems found while parsing, and holds the options being parsed with. In strict
+/// mode the first error is returned instead.
+struct Reporter<'a> {
+    path: Option<&'a Path>,
+    options: &'a ParseOptions,
+    diagnostics: Vec<Diagnostic>,
+    /// How many containers the line being parsed is inside.
+    depth: usize,
+    /// Each footnote reference and definition seen so far, with its line.
+    footnote_refs: Vec<(String, usize)>,
+    footnote_definitions: Vec<(String, usize)>,
+}
+
 
 // Implementations
 
-impl SynElement {
-    fn parse_line(line: String) -> Result<Self, ()> {
-        let line = line.trim().to_string();
-        if line == "---" {
-            Ok(SynElement::LineH)
-        } else if line.starts_with("#") {
-            Ok(SynElement::Heading(line[1..].to_string()))
-        } else if line.starts_with(".img ") {
-            let sections = line[5..].split("|").map(|e| e.to_string()).collect::<Vec<_>>();
-            if sections.len() == 3 {
-                Ok(SynElement::Image { path: sections[0].clone(), alt: sections[1].clone(), style: sections[2].clone() })
-            } else {
-                Err(())
-            }
-        } else {
-            Ok(SynElement::Text(line))
+impl HeaderField {
+    fn line(self) -> usize {
+        match self {
+            HeaderField::Title => 1,
+            HeaderField::Tags => 2,
+            HeaderField::Posted => 3,
+            HeaderField::Summary => 4,
         }
     }
+}
 
 
-    pub fn generate_tag(&self) -> String {
+impl fmt::Display for HeaderField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            SynElement::Text(text) => {
-                let text = text.replace("\n", "<br>");
-                format!("<p>{text}</p>")
-            },
-            SynElement::Heading(text) => format!("<h2>{text}</h2>"),
-            SynElement::Image { path, alt, style } => format!("<img src='{path}' style='{style}'>{alt}</img>"),
-            SynElement::LineH => "<div class='hline'></div>".into(),
+            HeaderField::Title => write!(f, "title"),
+            HeaderField::Tags => write!(f, "tags"),
+            HeaderField::Posted => write!(f, "posted date"),
+            HeaderField::Summary => write!(f, "summary"),
         }
     }
+}
 
 
-    fn generate_line(&self) -> String {
+impl SynError {
+    pub fn path(&self) -> Option<&Path> {
         match self {
-            SynElement::Text(text) => text.clone(),
-            SynElement::Heading(text) => format!("#{text}"),
-            SynElement::Image { path, alt, style } => format!(".img {path}|{alt}|{style}"),
-            SynElement::LineH => "---".into(),
+            SynError::Io { path, .. }
+            | SynError::InvalidUtf8 { path, .. }
+            | SynError::MissingHeader { path, .. }
+            | SynError::MalformedDirective { path, .. }
+            | SynError::MalformedTable { path, .. }
+            | SynError::MalformedContainer { path, .. }
+            | SynError::MalformedQuote { path, .. }
+            | SynError::MalformedList { path, .. }
+            | SynError::UnclosedBlock { path, .. }
+            | SynError::InvalidFootnote { path, .. }
+            | SynError::MalformedMath { path, .. }
+            | SynError::MalformedMetadata { path, .. } => path.as_deref(),
+            SynError::InvalidElement { .. } => None,
+        }
+    }
+
+
+    /// The line the error was found on, if it can be pinned to one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            SynError::Io { .. } | SynError::InvalidElement { .. } => None,
+            SynError::InvalidUtf8 { line, .. }
+            | SynError::MissingHeader { line, .. }
+            | SynError::MalformedDirective { line, .. }
+            | SynError::MalformedTable { line, .. }
+            | SynError::MalformedContainer { line, .. }
+            | SynError::MalformedQuote { line, .. }
+            | SynError::MalformedList { line, .. }
+            | SynError::UnclosedBlock { line, .. }
+            | SynError::InvalidFootnote { line, .. }
+            | SynError::MalformedMath { line, .. }
+            | SynError::MalformedMetadata { line, .. } => Some(*line),
+        }
+    }
+
+
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            SynError::Io { .. } | SynError::InvalidElement { .. } => None,
+            SynError::MissingHeader { .. } => Some(1),
+            SynError::InvalidUtf8 { column, .. }
+            | SynError::MalformedDirective { column, .. }
+            | SynError::MalformedTable { column, .. }
+            | SynError::MalformedContainer { column, .. }
+            | SynError::MalformedQuote { column, .. }
+            | SynError::MalformedList { column, .. }
+            | SynError::UnclosedBlock { column, .. }
+            | SynError::InvalidFootnote { column, .. }
+            | SynError::MalformedMath { column, .. }
+            | SynError::MalformedMetadata { column, .. } => Some(*column),
+        }
+    }
+
+
+    fn io(path: &Path, source: io::Error) -> Self {
+        SynError::Io { path: Some(path.to_path_buf()), source }
+    }
+
+
+    /// The error description without the location prefix.
+    pub fn message(&self) -> String {
+        match self {
+            SynError::Io { source, .. } => source.to_string(),
+            SynError::InvalidUtf8 { .. } => "invalid UTF-8".into(),
+            SynError::MissingHeader { field, .. } => format!("file ended before the {field} line"),
+            SynError::MalformedDirective { directive, message, .. } => format!("malformed .{directive} directive: {message}"),
+            SynError::MalformedTable { message, .. } => format!("malformed table: {message}"),
+            SynError::MalformedContainer { message, .. } => format!("malformed container: {message}"),
+            SynError::MalformedQuote { message, .. } => format!("malformed quote: {message}"),
+            SynError::MalformedList { message, .. } => format!("malformed list: {message}"),
+            SynError::UnclosedBlock { delimiter, .. } => format!("block opened with {delimiter} is never closed"),
+            SynError::InvalidFootnote { id, message, .. } => format!("footnote [^{id}] {message}"),
+            SynError::MalformedMath { message, .. } => format!("malformed math: {message}"),
+            SynError::MalformedMetadata { message, .. } => format!("malformed metadata: {message}"),
+            SynError::InvalidElement { message } => format!("invalid element: {message}"),
+        }
+    }
+
+
+    fn with_path(mut self, new_path: &Path) -> Self {
+        match &mut self {
+            SynError::Io { path, .. }
+            | SynError::InvalidUtf8 { path, .. }
+            | SynError::MissingHeader { path, .. }
+            | SynError::MalformedDirective { path, .. }
+            | SynError::MalformedTable { path, .. }
+            | SynError::MalformedContainer { path, .. }
+            | SynError::MalformedQuote { path, .. }
+            | SynError::MalformedList { path, .. }
+            | SynError::UnclosedBlock { path, .. }
+            | SynError::InvalidFootnote { path, .. }
+            | SynError::MalformedMath { path, .. }
+            | SynError::MalformedMetadata { path, .. } => *path = Some(new_path.to_path_buf()),
+            SynError::InvalidElement { .. } => {},
         }
+        self
     }
 }
 
 
-impl SynFile {
-    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, ()> {
-        let in_file = File::open(path);
-        if let Ok(in_file) = in_file {
-            let mut reader = BufReader::new(in_file);
-            let mut line = String::new();
-            reader.read_line(&mut line).unwrap();
-            let title = line.trim().to_string();
-            line.clear();
-            reader.read_line(&mut line).unwrap();
-            let tags = line.split(",").map(|e| e.trim().to_string()).collect::<Vec<_>>();
-            line.clear();
-            reader.read_line(&mut line).unwrap();
-            let posted = line.trim().to_string();
-            line.clear();
-            reader.read_line(&mut line).unwrap();
-            let summary = line.trim().to_string();
-
-            let mut elements = Vec::new();
-            while let Ok(len) = reader.read_line(&mut line) {
-                if line.len() > 0 {
-                    if let Ok(element) = SynElement::parse_line(line.clone()) {
-                        elements.push(element);
-                    }
+impl fmt::Display for SynError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = self.path() {
+            write!(f, "{}:", path.display())?;
+        }
+        if let (Some(line), Some(column)) = (self.line(), self.column()) {
+            write!(f, "{line}:{column}:")?;
+        }
+        if self.path().is_some() || self.line().is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message())
+    }
+}
 
-                    line.clear();
-                }
-                if len == 0 {
-                    break;
-                }
-            }
+
+impl Error for SynError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SynError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 
 
-            return Ok(Self {
-                title,
-                tags,
-                posted,
-                summary,
-                elements,
-            });
+impl Span {
+    fn line(line: usize, column: usize, text: &str) -> Self {
+        Self { line, column, end_line: line, end_column: text.trim_end().chars().count() + 1 }
+    }
+}
+
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}:{}: {severity}: {}", self.span.line, self.span.column, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (try `{suggestion}`)")?;
         }
+        Ok(())
+    }
+}
+
 
-        Err(())
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            recover: false,
+            directives: Directives::default(),
+            max_container_depth: 16,
+        }
     }
+}
 
 
-    pub fn load_file_metadata<P: AsRef<Path>>(path: P) -> Result<Self, ()> {
-        let in_file = File::open(path);
-        if let Ok(in_file) = in_file {
-            let mut reader = BufReader::new(in_file);
-            let mut line = String::new();
-            reader.read_line(&mut line).unwrap();
-            let title = line.trim().to_string();
-            line.clear();
-            reader.read_line(&mut line).unwrap();
-            let tags = line.split(",").map(|e| e.trim().to_string()).collect::<Vec<_>>();
-            line.clear();
-            reader.read_line(&mut line).unwrap();
-            let posted = line.trim().to_string();
-            line.clear();
-            reader.read_line(&mut line).unwrap();
-            let summary = line.trim().to_string();
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            trusted_html: false,
+            heading_base_level: 2,
+            highlighting: Highlighting::Off,
+            theme: Theme::default(),
+            callouts: [
+                ("note", "Note", "\u{2139}\u{fe0f}"),
+                ("tip", "Tip", "\u{1f4a1}"),
+                ("important", "Important", "\u{2757}"),
+                ("warning", "Warning", "\u{26a0}\u{fe0f}"),
+                ("caution", "Caution", "\u{1f6d1}"),
+            ].into_iter().map(|(kind, label, icon)| (kind.to_string(), CalloutKind { label: label.into(), icon: icon.into(), role: "note".into() })).collect(),
+            sidenotes: false,
+            embeds: [
+                ("youtube", "https://www.youtube.com/watch?v={id}", "https://www.youtube-nocookie.com/embed/{id}?autoplay=1", "Watch on YouTube"),
+                ("vimeo", "https://vimeo.com/{id}", "https://player.vimeo.com/video/{id}?dnt=1&autoplay=1", "Watch on Vimeo"),
+                ("mastodon", "https://{id}", "https://{id}/embed", "View on Mastodon"),
+            ].into_iter().map(|(name, link, frame, action)| (name.to_string(), EmbedProvider { link: link.into(), frame: frame.into(), action: action.into() })).collect(),
+            glossary: HashMap::new(),
+        }
+    }
+}
 
-            let elements = Vec::new();
 
-            return Ok(Self {
-                title,
-                tags,
-                posted,
-                summary,
-                elements,
-            });
+impl<'a> RenderContext<'a> {
+    fn new(options: &'a RenderOptions) -> Self {
+        Self {
+            options,
+            slugs: HashSet::new(),
+            footnotes: HashMap::new(),
+            footnote_order: Vec::new(),
+            footnote_refs: HashMap::new(),
+            glossary: Vec::new(),
+            glossed: HashSet::new(),
+            in_paragraph: false,
         }
-        Err(())
     }
 
 
-    pub fn save_file<P: AsRef<Path>>(&self, path: P) {
-        let out_file = File::create(path);
-        if let Ok(mut out_file) = out_file {
-            write!(out_file, "{}\n{}\n{}\n{}\n\n", self.title, self.tags.join(","), self.posted, self.summary).unwrap();
-            for element in &self.elements {
-                write!(out_file, "{}\n\n", element.generate_line()).unwrap();
+    /// Renders a reference to a footnote, numbering it if this is the first one. With sidenotes,
+    /// the footnote itself goes right after its first reference.
+    fn footnote_ref(&mut self, id: &str) -> String {
+        // Without a definition, say when rendering a single element, leave the reference as written.
+        let Some(content) = self.footnotes.get(id).copied() else {
+            return escape_html(&format!("[^{id}]"));
+        };
+        let number = match self.footnote_order.iter().position(|other| other == id) {
+            Some(index) => index + 1,
+            None => {
+                self.footnote_order.push(id.to_string());
+                self.footnote_order.len()
+            },
+        };
+        let count = self.footnote_refs.entry(id.to_string()).or_insert(0);
+        *count += 1;
+        let ref_id = match *count {
+            1 => format!("fnref-{number}"),
+            count => format!("fnref-{number}-{count}"),
+        };
+
+        match (self.options.sidenotes, *count) {
+            (true, 1) => {
+                let note = Inline::render(content, self);
+                format!("<sup class='sidenote-ref' id='{ref_id}'>{number}</sup><small class='sidenote' id='fn-{number}'><sup>{number}</sup> {note}</small>")
+            },
+            (true, _) => format!("<sup class='sidenote-ref' id='{ref_id}'><a href='#fn-{number}'>{number}</a></sup>"),
+            (false, _) => format!("<sup class='footnote-ref' id='{ref_id}'><a href='#fn-{number}'>{number}</a></sup>"),
+        }
+    }
+
+
+    /// The numbered list of footnotes referenced so far, each with links back to its references.
+    /// Empty with sidenotes, or if nothing was referenced.
+    fn footnote_section(&mut self) -> String {
+        if self.options.sidenotes || self.footnote_order.is_empty() {
+            return String::new();
+        }
+        // Footnotes can reference other footnotes, which adds them to the end as we go.
+        let mut items = Vec::new();
+        let mut index = 0;
+        while index < self.footnote_order.len() {
+            let id = self.footnote_order[index].clone();
+            let number = index + 1;
+            let content = Inline::render(self.footnotes[id.as_str()], self);
+            let backs = (1..=self.footnote_refs[&id]).map(|count| match count {
+                1 => format!("<a href='#fnref-{number}' class='footnote-back'>\u{21a9}</a>"),
+                count => format!("<a href='#fnref-{number}-{count}' class='footnote-back'>\u{21a9}<sup>{count}</sup></a>"),
+            }).collect::<Vec<_>>().join(" ");
+            items.push(format!("<li id='fn-{number}'>{content} {backs}</li>"));
+            index += 1;
+        }
+        format!("<section class='footnotes' role='doc-endnotes'><ol>{}</ol></section>", items.concat())
+    }
+
+
+    /// Escapes paragraph text, wrapping the first occurrence of each glossary term in it.
+    fn gloss(&mut self, text: &str) -> String {
+        let mut html = String::new();
+        let mut rest = text;
+        loop {
+            let found = self.glossary.iter()
+                .filter(|(term, _)| !self.glossed.contains(term))
+                .filter_map(|(term, definition)| Some((find_word(rest, term)?, term, definition)))
+                .min_by_key(|(at, term, _)| (*at, std::cmp::Reverse(term.len())))
+                .map(|(at, term, definition)| (at, term.clone(), definition.clone()));
+            let Some((at, term, definition)) = found else {
+                break;
+            };
+
+            let tag = match term.chars().any(char::is_lowercase) {
+                true => "dfn",
+                false => "abbr",
+            };
+            html.push_str(&escape_html(&rest[..at]));
+            html.push_str(&format!("<{tag} title='{}'>{}</{tag}>", escape_html(&definition), escape_html(&term)));
+            rest = &rest[at + term.len()..];
+            self.glossed.insert(term);
+        }
+        html.push_str(&escape_html(rest));
+        html
+    }
+
+
+    /// Turns heading text into a URL-friendly ID, unique within the document.
+    fn slug(&mut self, text: &str) -> String {
+        let mut slug = String::new();
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                slug.extend(c.to_lowercase());
+            } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
             }
         }
+        let slug = match slug.trim_end_matches('-') {
+            "" => "section".to_string(),
+            slug => slug.to_string(),
+        };
+
+        let mut unique = slug.clone();
+        let mut n = 1;
+        while !self.slugs.insert(unique.clone()) {
+            unique = format!("{slug}-{n}");
+            n += 1;
+        }
+        unique
     }
+}
 
 
-    pub fn get_title(&self) -> &String {
-        &self.title
+impl<R: BufRead> LineReader<R> {
+    fn new(reader: R) -> Self {
+        Self { reader, line: 0, unread: Vec::new() }
     }
-    pub fn get_tags(&self) -> &Vec<String> {
-        &self.tags
+
+
+    /// Reads the next line, including its line ending. Returns `None` at the end of the file.
+    fn read_line(&mut self, reporter: &mut Reporter) -> Result<Option<String>, SynError> {
+        if let Some(line) = self.unread.pop() {
+            self.line += 1;
+            return Ok(Some(line));
+        }
+        let mut bytes = Vec::new();
+        let len = self.reader.read_until(b'\n', &mut bytes).map_err(|e| SynError::Io { path: reporter.path.map(Path::to_path_buf), source: e })?;
+        if len == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+
+        match String::from_utf8(bytes) {
+            Ok(line) => Ok(Some(line)),
+            Err(e) => {
+                let valid = &e.as_bytes()[..e.utf8_error().valid_up_to()];
+                let column = String::from_utf8_lossy(valid).chars().count() + 1;
+                let line = String::from_utf8_lossy(e.as_bytes()).into_owned();
+                let error = SynError::InvalidUtf8 { path: None, line: self.line, column };
+                reporter.error(error, Span::line(self.line, column, &line), None)?;
+                Ok(Some(line))
+            },
+        }
     }
-    pub fn get_posted(&self) -> &String {
-        &self.posted
+
+
+    /// Puts lines back, in the order they were read, so they're read again next. Invalid UTF-8 in
+    /// them isn't reported a second time.
+    fn unread(&mut self, lines: Vec<String>) {
+        self.line -= lines.len();
+        self.unread.extend(lines.into_iter().rev());
     }
-    pub fn get_summary(&self) -> &String {
-        &self.summary
+
+
+    /// Reads one of the fixed header lines. A missing line is reported and treated as empty.
+    fn read_header_line(&mut self, field: HeaderField, reporter: &mut Reporter) -> Result<String, SynError> {
+        match self.read_line(reporter)? {
+            Some(line) => Ok(line),
+            None => {
+                let line = field.line();
+                let error = SynError::MissingHeader { path: None, field, line };
+                reporter.error(error, Span { line, column: 1, end_line: line, end_column: 1 }, None)?;
+                Ok(String::new())
+            },
+        }
     }
-    pub fn get_elements(&self) -> &Vec<SynElement> {
-        &self.elements
+}
+
+
+impl<'a> Reporter<'a> {
+    fn new(path: Option<&'a Path>, options: &'a ParseOptions) -> Self {
+        Self { path, options, diagnostics: Vec::new(), depth: 0, footnote_refs: Vec::new(), footnote_definitions: Vec::new() }
+    }
+
+
+    /// Records an error, or returns it if we aren't recovering.
+    fn error(&mut self, error: SynError, span: Span, suggestion: Option<String>) -> Result<(), SynError> {
+        let error = match self.path {
+            Some(path) => error.with_path(path),
+            None => error,
+        };
+        if !self.options.recover {
+            return Err(error);
+        }
+        self.diagnostics.push(Diagnostic { severity: Severity::Error, span, message: error.message(), suggestion });
+        Ok(())
+    }
+
+
+    fn warning(&mut self, span: Span, message: String, suggestion: Option<String>) {
+        self.diagnostics.push(Diagnostic { severity: Severity::Warning, span, message, suggestion });
     }
 }
 
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl SynElement {
+    #[cfg(test)]
+    fn parse_line(line: String) -> Result<Self, SynError> {
+        let lines = line.lines().enumerate().map(|(i, line)| (i + 1, line)).collect::<Vec<_>>();
+        let options = ParseOptions::default();
+        let mut reporter = Reporter::new(None, &options);
+        Ok(Self::parse_lines(&lines, &mut reporter)?.remove(0))
+    }
 
-    #[test]
-    fn text_element() {
-        let element = SynElement::parse_line("Hello,\nSynBlog!".into()).unwrap();
-        assert_eq!(element, SynElement::Text("Hello,\nSynBlog!".into()));
 
-        let as_line = element.generate_line();
-        assert_eq!(as_line, "Hello,\nSynBlog!".to_string());
+    /// Parses the body of a file. Headings, rules and directives take up a single line each, code
+    /// blocks run until their closing fence, and runs of any other lines form paragraphs, which
+    /// end at a blank line.
+    fn parse_lines(lines: &[(usize, &str)], reporter: &mut Reporter) -> Result<Vec<Self>, SynError> {
+        Self::parse_blocks(lines, false, reporter).map(|(elements, _, _)| elements)
+    }
 
-        let as_tag = element.generate_tag();
-        assert_eq!(as_tag, "<p>Hello,<br>SynBlog!</p>");
+
+    /// Parses elements until the end of `lines`, or if we're `in_container`, the `:::` closing it.
+    /// Returns the elements, how many lines were used including the closing line, and whether it
+    /// was found.
+    fn parse_blocks(lines: &[(usize, &str)], in_container: bool, reporter: &mut Reporter) -> Result<(Vec<Self>, usize, bool), SynError> {
+        let mut elements = Vec::new();
+        // Containers opened past the depth limit, whose closing lines we skip over.
+        let mut flattened = 0;
+        let mut i = 0;
+        while i < lines.len() {
+            let (line_no, line) = lines[i];
+            let column = line.chars().take_while(|c| c.is_whitespace()).count() + 1;
+            if line.trim().is_empty() {
+                i += 1;
+            } else if line.trim() == ":::" {
+                i += 1;
+                if flattened > 0 {
+                    flattened -= 1;
+                } else if in_container {
+                    return Ok((elements, i, true));
+                } else {
+                    let error = SynError::MalformedContainer { path: None, line: line_no, column, message: "`:::` doesn't close any container".into() };
+                    reporter.error(error, Span::line(line_no, column, line), Some(String::new()))?;
+                }
+            } else if let Some((name, title)) = Self::parse_container_open(line) {
+                i += 1;
+                if reporter.depth >= reporter.options.max_container_depth {
+                    let message = format!("containers are nested more than {} deep", reporter.options.max_container_depth);
+                    let error = SynError::MalformedContainer { path: None, line: line_no, column, message };
+                    reporter.error(error, Span::line(line_no, column, line), None)?;
+                    flattened += 1;
+                    continue;
+                }
+
+                reporter.depth += 1;
+                let (children, used, closed) = Self::parse_blocks(&lines[i..], true, reporter)?;
+                reporter.depth -= 1;
+                if !closed {
+                    let error = SynError::UnclosedBlock { path: None, line: line_no, column, delimiter: ":::".into() };
+                    reporter.error(error, Span::line(line_no, column, line), None)?;
+                }
+                Self::push_element(&mut elements, SynElement::Container { name: name.into(), title: Inline::parse(title), children }, (line_no, line), reporter)?;
+                i += used;
+            } else if line.trim() == "$$" {
+                let end = match lines[i + 1..].iter().position(|(_, line)| line.trim() == "$$") {
+                    Some(end) => i + 1 + end,
+                    None => {
+                        let error = SynError::UnclosedBlock { path: None, line: line_no, column, delimiter: "$$".into() };
+                        reporter.error(error, Span::line(line_no, column, line), Some(format!("{line}\n...\n$$")))?;
+                        lines.len()
+                    },
+                };
+                let source = lines[i + 1..end].iter().map(|(_, line)| *line).collect::<Vec<_>>().join("\n");
+                Self::push_element(&mut elements, SynElement::Math { source }, (line_no, line), reporter)?;
+                i = end + 1;
+            } else if let Some((fence, lang)) = Self::parse_fence(line) {
+                let closed = lines[i + 1..].iter().position(|(_, line)| {
+                    let line = line.trim();
+                    line.len() >= fence && line.chars().all(|c| c == '`')
+                });
+                let end = match closed {
+                    Some(end) => i + 1 + end,
+                    None => {
+                        let delimiter = "`".repeat(fence);
+                        let error = SynError::UnclosedBlock { path: None, line: line_no, column, delimiter: delimiter.clone() };
+                        reporter.error(error, Span::line(line_no, column, line), Some(format!("{line}\n...\n{delimiter}")))?;
+                        lines.len()
+                    },
+                };
+                let source = lines[i + 1..end].iter().map(|(_, line)| *line).collect::<Vec<_>>().join("\n");
+                Self::push_element(&mut elements, SynElement::Code { lang: lang.to_string(), source }, (line_no, line), reporter)?;
+                i = end + 1;
+            } else if List::parse_marker(line).is_some() {
+                // Lines that aren't items continue the item before them, up until a blank line or
+                // another kind of block.
+                let end = (i..lines.len()).find(|j| {
+                    let line = lines[*j].1;
+                    line.trim().is_empty() || (!Self::is_text_line(line, &reporter.options.directives) && List::parse_marker(line).is_none())
+                });
+                let block = &lines[i..end.unwrap_or(lines.len())];
+                let mut pos = 0;
+                while pos < block.len() {
+                    Self::push_element(&mut elements, SynElement::List(List::parse(block, &mut pos, reporter)?), (line_no, line), reporter)?;
+                }
+                i += block.len();
+            } else if let Some((true, _)) = Definition::parse_marker(line) {
+                let end = (i..lines.len()).find(|j| {
+                    let line = lines[*j].1;
+                    line.trim().is_empty() || (!Self::is_text_line(line, &reporter.options.directives) && Definition::parse_marker(line).is_none())
+                });
+                let block = &lines[i..end.unwrap_or(lines.len())];
+                Self::push_element(&mut elements, SynElement::DefinitionList(Definition::parse(block)), (line_no, line), reporter)?;
+                i += block.len();
+            } else if line.trim_start().starts_with('|') {
+                let end = (i..lines.len()).find(|j| !lines[*j].1.trim_start().starts_with('|')).unwrap_or(lines.len());
+                Self::push_element(&mut elements, SynElement::Table(Table::parse(&lines[i..end], reporter)?), (line_no, line), reporter)?;
+                i = end;
+            } else if Self::strip_quote(line).is_some() {
+                let start = i;
+                let mut quoted = Vec::new();
+                while let Some(line) = lines.get(i).and_then(|(line_no, line)| Some((*line_no, Self::strip_quote(line)?))) {
+                    quoted.push(line);
+                    i += 1;
+                }
+                // Past the limit, the quote is kept as text, markers and all, rather than recursing
+                // any deeper.
+                if reporter.depth >= reporter.options.max_container_depth {
+                    let message = format!("quotes are nested more than {} deep", reporter.options.max_container_depth);
+                    let error = SynError::MalformedQuote { path: None, line: line_no, column, message };
+                    reporter.error(error, Span::line(line_no, column, line), None)?;
+                    let text = lines[start..i].iter().map(|(_, line)| line.trim()).collect::<Vec<_>>().join("\n");
+                    Self::push_element(&mut elements, SynElement::Text(Inline::parse(&text)), (line_no, line), reporter)?;
+                    continue;
+                }
+
+                reporter.depth += 1;
+                if let Some((kind, title)) = Self::parse_callout_marker(quoted[0].1) {
+                    let children = Self::parse_lines(&quoted[1..], reporter)?;
+                    reporter.depth -= 1;
+                    Self::push_element(&mut elements, SynElement::Callout { kind, title: Inline::parse(title), children }, (line_no, line), reporter)?;
+                    continue;
+                }
+                let children = Self::parse_lines(&quoted, reporter)?;
+                reporter.depth -= 1;
+
+                let credit = lines.get(i)
+                    .and_then(|(_, line)| line.trim().strip_prefix("--"))
+                    .filter(|credit| credit.is_empty() || credit.starts_with(' '));
+                let (attribution, cite) = match credit {
+                    Some(credit) => {
+                        i += 1;
+                        // The name is escaped like a directive section, so it can hold a `|`.
+                        let name = split_sections(credit)[0];
+                        let cite = credit.get(name.len() + 1..).map(|cite| cite.trim().to_string());
+                        let name = unescape_section(name.trim());
+                        (Some(name).filter(|name| !name.is_empty()), cite)
+                    },
+                    None => (None, None),
+                };
+                Self::push_element(&mut elements, SynElement::Quote { children, attribution, cite }, (line_no, line), reporter)?;
+            } else if let Some((id, rest)) = Self::parse_footnote_definition(line) {
+                let mut source = vec![rest];
+                i += 1;
+                while let Some((_, line)) = lines.get(i).filter(|(_, line)| !line.trim().is_empty() && Self::is_text_line(line, &reporter.options.directives)) {
+                    source.push(line.trim());
+                    i += 1;
+                }
+                Self::push_element(&mut elements, SynElement::Footnote { id: id.into(), content: Inline::parse(&source.join("\n")) }, (line_no, line), reporter)?;
+            } else if !Self::is_text_line(line, &reporter.options.directives) {
+                Self::push_element(&mut elements, Self::parse_line_at(line, line_no, reporter)?, (line_no, line), reporter)?;
+                i += 1;
+            } else {
+                let mut source = Vec::new();
+                while let Some((_, line)) = lines.get(i).filter(|(_, line)| !line.trim().is_empty() && Self::is_text_line(line, &reporter.options.directives)) {
+                    source.push(line.trim());
+                    i += 1;
+                }
+                Self::push_element(&mut elements, SynElement::Text(Inline::parse(&source.join("\n"))), (line_no, line), reporter)?;
+            }
+        }
+        Ok((elements, i, false))
     }
 
-    #[test]
-    fn img_element() {
-        let element = SynElement::parse_line(".img test.png|A test image!|width:100%".into()).unwrap();
-        assert_eq!(element, SynElement::Image { path: "test.png".into(), alt: "A test image!".into(), style: "width:100%".into() });
 
-        let as_line = element.generate_line();
-        assert_eq!(as_line, ".img test.png|A test image!|width:100%".to_string());
+    /// Adds a parsed element starting at `line`, checking its math and noting the footnotes it
+    /// references or defines for checking later.
+    fn push_element(elements: &mut Vec<Self>, element: Self, (line_no, line): (usize, &str), reporter: &mut Reporter) -> Result<(), SynError> {
+        let mut sources = Vec::new();
+        if let SynElement::Math { source } = &element {
+            sources.push(source.as_str());
+        }
+        for content in element.inline_content() {
+            Inline::visit(content, &mut |inline| match inline {
+                Inline::FootnoteRef(id) => reporter.footnote_refs.push((id.clone(), line_no)),
+                Inline::Math(tex) => sources.push(tex),
+                _ => {},
+            });
+        }
+        for source in sources {
+            if let Err(message) = math::validate(source) {
+                let column = line.chars().take_while(|c| c.is_whitespace()).count() + 1;
+                let error = SynError::MalformedMath { path: None, line: line_no, column, message };
+                reporter.error(error, Span::line(line_no, column, line), None)?;
+            }
+        }
 
-        let as_tag = element.generate_tag();
-        assert_eq!(as_tag, "<img src='test.png' style='width:100%'>A test image!</img>");
+        if let SynElement::Footnote { id, .. } = &element {
+            reporter.footnote_definitions.push((id.clone(), line_no));
+        }
+        elements.push(element);
+        Ok(())
     }
 
-    #[test]
-    fn heading_element() {
-        let element = SynElement::parse_line("#Big Title".into()).unwrap();
-        assert_eq!(element, SynElement::Heading("Big Title".into()));
 
-        let as_line = element.generate_line();
-        assert_eq!(as_line, "#Big Title".to_string());
+    fn is_paragraph_line(line: &str) -> bool {
+        let trimmed = line.trim();
+        trimmed != "---" && !trimmed.starts_with("#") && split_directive(line).is_none() && trimmed != ":::" && Self::parse_container_open(line).is_none()
+            && !trimmed.starts_with('>') && !trimmed.starts_with('|') && Self::parse_fence(line).is_none() && List::parse_marker(line).is_none()
+            && Self::parse_footnote_definition(line).is_none() && trimmed != "$$" && Definition::parse_marker(line).is_none()
+    }
 
-        let as_tag = element.generate_tag();
-        assert_eq!(as_tag, "<h2>Big Title</h2>");
+
+    /// Whether a line is paragraph text while parsing. Unlike `is_paragraph_line`, which errs on
+    /// the side of escaping, this knows which directives exist, so a line like `.net rocks` that
+    /// only looks like one is still text. So is a bare `.img`, or any other directive name without
+    /// the arguments it needs, as it was before there were other directives.
+    fn is_text_line(line: &str, directives: &Directives) -> bool {
+        let is_text = |(name, args): (&str, &str)| match directives.get(name) {
+            Some(directive) => args.trim().is_empty() && directive.positional().len() > directive.optional(),
+            None => true,
+        };
+        Self::is_paragraph_line(line) || split_directive(line).is_some_and(is_text)
     }
 
-    #[test]
-    fn lineh_element() {
-        let element = SynElement::parse_line("---".into()).unwrap();
-        assert_eq!(element, SynElement::LineH);
 
-        let as_line = element.generate_line();
-        assert_eq!(as_line, "---".to_string());
+    /// Reads the `[!KIND] title` line that turns a quote into a callout, returning the kind in
+    /// lowercase and the title.
+    fn parse_callout_marker(line: &str) -> Option<(String, &str)> {
+        let (kind, title) = line.trim().strip_prefix("[!")?.split_once(']')?;
+        let valid = !kind.is_empty() && kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        (valid && (title.is_empty() || title.starts_with(' '))).then(|| (kind.to_ascii_lowercase(), title.trim()))
+    }
 
-        let as_tag = element.generate_tag();
-        assert_eq!(as_tag, "<div class='hline'></div>");
+
+    /// Reads the start of a `[^id]: text` footnote definition, returning the ID and the text.
+    fn parse_footnote_definition(line: &str) -> Option<(&str, &str)> {
+        let rest = line.trim().strip_prefix("[^")?;
+        let end = rest.find(|c| !is_footnote_id_char(c)).unwrap_or(rest.len());
+        let text = rest[end..].strip_prefix("]:")?;
+        (end > 0).then(|| (&rest[..end], text.trim()))
+    }
+
+
+    /// Reads a `:::name title` line, returning the name and title.
+    fn parse_container_open(line: &str) -> Option<(&str, &str)> {
+        let rest = line.trim().strip_prefix(":::")?;
+        let (name, title) = rest.split_once(' ').unwrap_or((rest, ""));
+        is_identifier(name).then_some((name, title.trim()))
+    }
+
+
+    /// The rest of a quoted line, after the `>` and the space following it.
+    fn strip_quote(line: &str) -> Option<&str> {
+        let rest = line.trim_start().strip_prefix('>')?;
+        Some(rest.strip_prefix(' ').unwrap_or(rest))
+    }
+
+
+    /// Reads an opening code fence: three or more backticks, then the language. Returns the fence
+    /// length and the language. Like in Markdown, a backtick after the fence means it's a code
+    /// span instead.
+    fn parse_fence(line: &str) -> Option<(usize, &str)> {
+        let line = line.trim();
+        let fence = line.chars().take_while(|c| *c == '`').count();
+        let lang = &line[fence..];
+        match fence >= 3 && !lang.contains('`') {
+            true => Some((fence, lang.trim())),
+            false => None,
+        }
+    }
+
+
+    /// Escapes the start of any line that would otherwise be read as something other than
+    /// paragraph text, by putting a backslash before its first punctuation character. Leading
+    /// whitespace is escaped too, since it would be trimmed away.
+    fn escape_line_starts(source: &str) -> String {
+        let source = source.strip_suffix('\n').unwrap_or(source);
+        let lines = source.split('\n').map(|line| {
+            if line.starts_with(char::is_whitespace) {
+                format!("\\{line}")
+            } else if let Some((id, _)) = Self::parse_footnote_definition(line) {
+                // Escaping the `[` would break up the reference, so escape the `:` after it.
+                let colon = id.len() + 3;
+                format!("{}\\{}", &line[..colon], &line[colon..])
+            } else if Self::is_paragraph_line(line) {
+                line.to_string()
+            } else {
+                match line.find(|c: char| c.is_ascii_punctuation()) {
+                    Some(i) => format!("{}\\{}", &line[..i], &line[i..]),
+                    None => line.to_string(),
+                }
+            }
+        });
+        lines.collect::<Vec<_>>().join("\n")
+    }
+
+
+    fn parse_line_at(raw: &str, line_no: usize, reporter: &mut Reporter) -> Result<Self, SynError> {
+        let indent = raw.chars().take_while(|c| c.is_whitespace()).count();
+        let line = raw.trim().to_string();
+        if line == "---" {
+            Ok(SynElement::LineH)
+        } else if line.starts_with("#") {
+            let level = line.chars().take_while(|c| *c == '#').count().min(6);
+            let content = &line[level..];
+            Ok(SynElement::Heading { level: level as u8, content: Inline::parse(content.strip_prefix(' ').unwrap_or(content)) })
+        } else if let Some((name, args, directive)) = split_directive(&line).and_then(|(name, args)| Some((name, args, reporter.options.directives.get(name)?.clone()))) {
+            let span = Span::line(line_no, indent + name.len() + 3, raw);
+            let args = DirectiveArgs::parse(args, directive.as_ref(), span, reporter)?;
+            if let Err(message) = directive.validate(&args) {
+                let error = SynError::MalformedDirective { path: None, line: span.line, column: span.column, directive: name.into(), message };
+                reporter.error(error, span, None)?;
+            }
+
+            let element = directive.to_element(&args).unwrap_or_else(|| SynElement::Custom(CustomElement::new(directive.clone(), args)));
+            match &element {
+                SynElement::Image { path, .. } if path.trim().is_empty() => reporter.warning(span, "image has no path".into(), None),
+                SynElement::Figure { path, .. } if path.trim().is_empty() => reporter.warning(span, "figure has no image path".into(), None),
+                _ => {},
+            }
+            Ok(element)
+        } else {
+            Ok(SynElement::Text(Inline::parse(&line)))
+        }
+    }
+
+
+    pub fn generate_tag(&self) -> String {
+        self.generate_tag_with(&RenderOptions::default())
+    }
+
+
+    pub fn generate_tag_with(&self, options: &RenderOptions) -> String {
+        self.render(&mut RenderContext::new(options))
+    }
+
+
+    fn render(&self, context: &mut RenderContext) -> String {
+        let options = context.options;
+        let text = |text: &str| match options.trusted_html {
+            true => text.to_string(),
+            false => escape_html(text),
+        };
+        match self {
+            SynElement::Text(content) => {
+                context.in_paragraph = true;
+                let html = Inline::render(content, context);
+                context.in_paragraph = false;
+                format!("<p>{html}</p>")
+            },
+            SynElement::Heading { level, content } => {
+                let tag = options.heading_base_level.clamp(1, 6).saturating_add((*level).max(1) - 1).min(6);
+                let id = context.slug(&Inline::plain_text(content));
+                format!("<h{tag} id='{}'>{}</h{tag}>", escape_html(&id), Inline::render(content, context))
+            },
+            SynElement::Image { path, alt, style } => image_tag(path, alt, style, options),
+            SynElement::Figure { path, alt, style, caption, credit } => {
+                let credit = match credit {
+                    Some(credit) => format!(" <small class='credit'>{}</small>", text(credit)),
+                    None => String::new(),
+                };
+                format!("<figure>{}<figcaption>{}{credit}</figcaption></figure>", image_tag(path, alt, style, options), text(caption))
+            },
+            SynElement::LineH => "<div class='hline'></div>".into(),
+            SynElement::Code { lang, source } => match lang.split_whitespace().next() {
+                Some(lang) => format!("<pre><code class='language-{}'>{}</code></pre>", escape_html(lang), highlight::highlight(lang, source, options)),
+                None => format!("<pre><code>{}</code></pre>", escape_html(source)),
+            },
+            SynElement::List(list) => list.render(context),
+            SynElement::Quote { children, attribution, cite } => {
+                let cite = cite.as_ref().map(|cite| match options.trusted_html {
+                    true => escape_html(cite),
+                    false => escape_html(&sanitize_url(cite)),
+                });
+                let children = Self::render_all(children, context);
+                let footer = match (attribution, &cite) {
+                    (Some(name), Some(cite)) => format!("<footer>\u{2014} <cite><a href='{cite}'>{}</a></cite></footer>", text(name)),
+                    (Some(name), None) => format!("<footer>\u{2014} <cite>{}</cite></footer>", text(name)),
+                    (None, _) => String::new(),
+                };
+                match cite {
+                    Some(cite) => format!("<blockquote cite='{cite}'>{children}{footer}</blockquote>"),
+                    None => format!("<blockquote>{children}{footer}</blockquote>"),
+                }
+            },
+            SynElement::Table(table) => table.render(context),
+            SynElement::Custom(custom) => custom.render(options),
+            SynElement::Callout { kind, title, children } => {
+                let fallback;
+                let presentation = match options.callouts.get(kind) {
+                    Some(presentation) => presentation,
+                    None => {
+                        let mut label = kind.clone();
+                        if let Some(first) = label.get_mut(..1) {
+                            first.make_ascii_uppercase();
+                        }
+                        fallback = CalloutKind { label, icon: String::new(), role: "note".into() };
+                        &fallback
+                    },
+                };
+                let icon = match presentation.icon.as_str() {
+                    "" => String::new(),
+                    icon => format!("<span class='callout-icon' aria-hidden='true'>{icon}</span> "),
+                };
+                let title = match title.is_empty() {
+                    true => escape_html(&presentation.label),
+                    false => Inline::render(title, context),
+                };
+                let children = Self::render_all(children, context);
+                format!(
+                    "<div class='callout callout-{}' role='{}'><p class='callout-title'>{icon}{title}</p>{children}</div>",
+                    escape_html(kind),
+                    escape_html(&presentation.role),
+                )
+            },
+            SynElement::Container { name, title, children } => {
+                let title = Inline::render(title, context);
+                let children = Self::render_all(children, context);
+                match (name.as_str(), title.as_str()) {
+                    ("details", title) => format!("<details><summary>{title}</summary>{children}</details>"),
+                    (name, "") => format!("<div class='{}'>{children}</div>", escape_html(name)),
+                    (name, title) => format!("<div class='{}'><div class='title'>{title}</div>{children}</div>", escape_html(name)),
+                }
+            },
+            // Definitions are gathered into the footnote section, or shown as sidenotes.
+            SynElement::Footnote { .. } => String::new(),
+            SynElement::Math { source } => math::render(source, true),
+            SynElement::Media(media) => media.render(options),
+            SynElement::DefinitionList(definitions) => {
+                let items = definitions.iter().map(|definition| {
+                    let term = format!("<dt>{}</dt>", Inline::render(&definition.term, context));
+                    let details = definition.details.iter().map(|detail| format!("<dd>{}</dd>", Inline::render(detail, context)));
+                    term + &details.collect::<String>()
+                });
+                format!("<dl>{}</dl>", items.collect::<String>())
+            },
+            SynElement::Embed { provider, id, thumbnail, title } => {
+                let thumbnail = match (thumbnail.as_str(), options.trusted_html) {
+                    ("", _) => String::new(),
+                    (path, true) => format!("<img src='{}' alt='' loading='lazy'>", escape_html(path)),
+                    (path, false) => format!("<img src='{}' alt='' loading='lazy'>", escape_html(&sanitize_url(path))),
+                };
+                let mut content = vec![thumbnail];
+                if !title.is_empty() {
+                    content.push(format!("<span class='embed-title'>{}</span>", text(title)));
+                }
+                let class = format!("embed embed-{}", escape_html(provider));
+                match options.embeds.get(provider) {
+                    Some(embed) => {
+                        let [link, frame] = [&embed.link, &embed.frame].map(|template| escape_html(&sanitize_url(&template.replace("{id}", id))));
+                        content.push(format!("<span class='embed-action'>{}</span>", escape_html(&embed.action)));
+                        format!("<div class='{class}' data-embed-src='{frame}'><a class='embed-link' href='{link}'>{}</a></div>", content.concat())
+                    },
+                    None => format!("<div class='{class}'>{}</div>", content.concat()),
+                }
+            },
+        }
+    }
+
+
+    /// Renders elements in order, one per line, leaving out the ones that render to nothing.
+    fn render_all(elements: &[SynElement], context: &mut RenderContext) -> String {
+        let html = elements.iter().map(|element| element.render(context));
+        html.filter(|html| !html.is_empty()).collect::<Vec<_>>().join("\n")
+    }
+
+
+    /// The inline content belonging to the element itself, not counting its child elements.
+    fn inline_content(&self) -> Vec<&[Inline]> {
+        fn list_content<'a>(list: &'a List, content: &mut Vec<&'a [Inline]>) {
+            for item in &list.items {
+                content.push(&item.content);
+                for child in &item.children {
+                    list_content(child, content);
+                }
+            }
+        }
+
+        let mut content = Vec::new();
+        match self {
+            SynElement::Text(inlines) | SynElement::Heading { content: inlines, .. } | SynElement::Footnote { content: inlines, .. } => content.push(inlines.as_slice()),
+            SynElement::Callout { title, .. } | SynElement::Container { title, .. } => content.push(title.as_slice()),
+            SynElement::List(list) => list_content(list, &mut content),
+            SynElement::Table(table) => content.extend(table.header.iter().chain(table.rows.iter().flatten()).map(Vec::as_slice)),
+            SynElement::Image { .. } | SynElement::Figure { .. } | SynElement::LineH | SynElement::Code { .. } | SynElement::Math { .. } | SynElement::Quote { .. }
+            | SynElement::Custom(_) | SynElement::Media(_) | SynElement::Embed { .. } => {},
+            SynElement::DefinitionList(definitions) => {
+                for definition in definitions {
+                    content.push(&definition.term);
+                    content.extend(definition.details.iter().map(Vec::as_slice));
+                }
+            },
+        }
+        content
+    }
+
+
+    fn children(&self) -> &[SynElement] {
+        match self {
+            SynElement::Quote { children, .. } | SynElement::Callout { children, .. } | SynElement::Container { children, .. } => children,
+            _ => &[],
+        }
+    }
+
+
+    /// Puts the elements in the form reading them back would give: inline content as
+    /// `Inline::normalize` leaves it, with line breaks in headings, table cells and titles turned
+    /// into spaces, directive sections, quote credits and code languages put on one trimmed line,
+    /// heading levels kept to 1 to 6, callout kinds in lowercase, nested lists of the same kind
+    /// that follow each other merged, and paragraphs and lists left without any content dropped.
+    fn normalize(elements: &mut Vec<SynElement>) {
+        let line = |text: &str| text.replace('\n', " ").trim().to_string();
+        for element in elements.iter_mut() {
+            for section in element.sections_mut() {
+                *section = line(section);
+            }
+            let (contents, single_line): (Vec<&mut Vec<Inline>>, bool) = match element {
+                SynElement::Text(inlines) | SynElement::Footnote { content: inlines, .. } => (vec![inlines], false),
+                SynElement::Heading { level, content } => {
+                    *level = (*level).clamp(1, 6);
+                    (vec![content], true)
+                },
+                SynElement::Callout { title, children, .. } | SynElement::Container { title, children, .. } => {
+                    Self::normalize(children);
+                    (vec![title], true)
+                },
+                SynElement::Quote { children, attribution, .. } => {
+                    Self::normalize(children);
+                    if attribution.as_deref() == Some("") {
+                        *attribution = None;
+                    }
+                    (Vec::new(), false)
+                },
+                SynElement::List(list) => {
+                    list.normalize();
+                    (list.content_mut(), false)
+                },
+                SynElement::Table(table) => (table.header.iter_mut().chain(table.rows.iter_mut().flatten()).collect(), true),
+                SynElement::DefinitionList(definitions) => (definitions.iter_mut().flat_map(|d| std::iter::once(&mut d.term).chain(d.details.iter_mut())).collect(), false),
+                SynElement::Code { lang, source } => {
+                    *lang = line(lang);
+                    *source = source.split('\n').map(|line| line.trim_end_matches('\r')).collect::<Vec<_>>().join("\n");
+                    (Vec::new(), false)
+                },
+                SynElement::Math { source } => {
+                    *source = source.split('\n').map(|line| line.trim_end_matches('\r')).collect::<Vec<_>>().join("\n");
+                    (Vec::new(), false)
+                },
+                _ => (Vec::new(), false),
+            };
+            for content in contents {
+                *content = match single_line {
+                    true => Inline::normalize_line(std::mem::take(content)),
+                    false => Inline::normalize(std::mem::take(content)),
+                };
+            }
+            if let SynElement::Callout { kind, .. } = element {
+                *kind = kind.to_ascii_lowercase();
+            }
+        }
+        elements.retain(|element| match element {
+            SynElement::Text(content) => !content.is_empty(),
+            SynElement::List(list) => !list.items.is_empty(),
+            SynElement::DefinitionList(definitions) => !definitions.is_empty(),
+            _ => true,
+        });
+    }
+
+
+    /// Checks that the elements can be saved in a form that loads back the same, once they've been
+    /// normalized.
+    fn validate(elements: &[SynElement], directives: &Directives) -> Result<(), SynError> {
+        let invalid = |message: String| SynError::InvalidElement { message };
+        for element in elements {
+            for content in element.inline_content() {
+                Inline::validate(content).map_err(invalid)?;
+            }
+            if let Some((name, _, args)) = element.directive_args() {
+                let directive = match element {
+                    SynElement::Custom(custom) => Some(custom.get_directive()),
+                    _ => directives.get(name),
+                };
+                args.check_sections()
+                    .and_then(|()| directive.map_or(Ok(()), |directive| directive.validate(&args)))
+                    .map_err(|message| invalid(format!(".{name} directive {message}")))?;
+            }
+            match element {
+                SynElement::Quote { attribution: Some(attribution), cite: Some(_), .. } if attribution.ends_with('\\') => {
+                    return Err(invalid(format!("quote attribution '{attribution}' ends in a backslash, which would escape the `|` before the cite")));
+                },
+                SynElement::Code { lang, .. } if lang.contains('`') => return Err(invalid(format!("code language '{lang}' has a backtick, which would end the fence"))),
+                SynElement::Math { source } => {
+                    if source.split('\n').any(|line| line.trim() == "$$") {
+                        return Err(invalid("math block has a `$$` line, which would close it".into()));
+                    }
+                    math::validate(source).map_err(|message| invalid(format!("malformed math: {message}")))?;
+                },
+                SynElement::Container { name, .. } if !is_identifier(name) => return Err(invalid(format!("'{name}' isn't a container name"))),
+                SynElement::Callout { kind, .. } if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') => {
+                    return Err(invalid(format!("'{kind}' isn't a callout kind")));
+                },
+                SynElement::Table(table) => table.validate().map_err(|message| invalid(format!("table {message}")))?,
+                SynElement::Footnote { id, .. } if id.is_empty() || !id.chars().all(is_footnote_id_char) => return Err(invalid(format!("'{id}' isn't a footnote ID"))),
+                SynElement::Media(media) => {
+                    if let Some(track) = media.tracks.iter().find(|track| track.path.contains(';') || track.lang.contains(';')) {
+                        return Err(invalid(format!("track '{}' has a `;` in its path or language", track.path)));
+                    }
+                },
+                _ => {},
+            }
+            Self::validate(element.children(), directives)?;
+        }
+        Ok(())
+    }
+
+
+    /// Checks that every footnote referenced is defined exactly once, and every one defined is
+    /// referenced, as loading the elements back would.
+    fn validate_footnotes(elements: &[SynElement]) -> Result<(), SynError> {
+        fn collect<'a>(elements: &'a [SynElement], refs: &mut Vec<&'a str>, definitions: &mut Vec<&'a str>) {
+            for element in elements {
+                for content in element.inline_content() {
+                    Inline::visit(content, &mut |inline| if let Inline::FootnoteRef(id) = inline {
+                        refs.push(id);
+                    });
+                }
+                if let SynElement::Footnote { id, .. } = element {
+                    definitions.push(id);
+                }
+                collect(element.children(), refs, definitions);
+            }
+        }
+
+        let (mut refs, mut definitions) = (Vec::new(), Vec::new());
+        collect(elements, &mut refs, &mut definitions);
+        let mut seen = HashSet::new();
+        let problem = definitions.iter().find_map(|id| match seen.insert(*id) {
+            false => Some((*id, "is defined more than once")),
+            true => (!refs.contains(id)).then_some((*id, "is never referenced")),
+        });
+        match problem.or_else(|| refs.iter().find(|id| !seen.contains(*id)).map(|id| (*id, "is never defined"))) {
+            Some((id, message)) => Err(SynError::InvalidElement { message: format!("footnote [^{id}] {message}") }),
+            None => Ok(()),
+        }
+    }
+
+
+    /// The directive line an element is saved as, if it's one: the directive's name and named
+    /// keys, and the arguments.
+    fn directive_args(&self) -> Option<(&str, &[&str], DirectiveArgs)> {
+        let positional = |sections: &[&String]| DirectiveArgs { positional: sections.iter().map(|section| section.to_string()).collect(), named: Vec::new() };
+        match self {
+            SynElement::Image { path, alt, style } => Some(("img", &[], positional(&[path, alt, style]))),
+            SynElement::Figure { path, alt, style, caption, credit } => {
+                let sections = [path, alt, style, caption].into_iter().chain(credit).collect::<Vec<_>>();
+                Some(("figure", &[], positional(&sections)))
+            },
+            SynElement::Media(media) => Some((media.kind.name(), Media::KEYS, media.to_args())),
+            SynElement::Embed { provider, id, thumbnail, title } => {
+                let mut sections = vec![provider, id];
+                if !thumbnail.is_empty() || !title.is_empty() {
+                    sections.push(thumbnail);
+                }
+                if !title.is_empty() {
+                    sections.push(title);
+                }
+                Some(("embed", &[], positional(&sections)))
+            },
+            SynElement::Custom(custom) => Some((custom.get_name(), custom.get_directive().named(), custom.get_args().clone())),
+            _ => None,
+        }
+    }
+
+
+    /// The text fields written on a single line, like directive sections and quote credits.
+    fn sections_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            SynElement::Image { path, alt, style } => vec![path, alt, style],
+            SynElement::Figure { path, alt, style, caption, credit } => [path, alt, style, caption].into_iter().chain(credit).collect(),
+            SynElement::Quote { attribution, cite, .. } => attribution.iter_mut().chain(cite).collect(),
+            SynElement::Custom(custom) => custom.sections_mut(),
+            SynElement::Media(media) => {
+                let tracks = media.tracks.iter_mut().flat_map(|track| [&mut track.path, &mut track.lang, &mut track.label]);
+                media.sources.iter_mut().chain(&mut media.poster).chain(tracks).chain([&mut media.fallback]).collect()
+            },
+            SynElement::Embed { provider, id, thumbnail, title } => vec![provider, id, thumbnail, title],
+            _ => Vec::new(),
+        }
+    }
+
+
+    fn generate_line(&self) -> String {
+        match self {
+            SynElement::Text(content) => Self::escape_line_starts(&Inline::generate_line(content)),
+            SynElement::Heading { level, content } => {
+                let line = Inline::generate_line(content);
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                // A space there would be taken as the one after the `#`s.
+                let escape = if line.starts_with(['#', ' ']) { "\\" } else { "" };
+                format!("{}{escape}{line}", "#".repeat(*level as usize))
+            },
+            SynElement::Image { .. } | SynElement::Figure { .. } | SynElement::Media(_) | SynElement::Embed { .. } => {
+                let (name, keys, args) = self.directive_args().expect("built-in directive elements have arguments");
+                args.generate_line(name, keys)
+            },
+            SynElement::LineH => "---".into(),
+            SynElement::Code { lang, source } => {
+                // The fence has to be longer than any line of the source that could close it.
+                let longest = source.split('\n')
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && line.chars().all(|c| c == '`'))
+                    .map(str::len)
+                    .max()
+                    .unwrap_or(0);
+                let fence = "`".repeat(longest.max(2) + 1);
+                format!("{fence}{lang}\n{source}\n{fence}")
+            },
+            SynElement::List(list) => {
+                let mut lines = Vec::new();
+                list.generate_lines(0, &mut lines);
+                lines.join("\n")
+            },
+            SynElement::Quote { children, attribution, cite } => {
+                let source = children.iter().map(|child| child.generate_line()).collect::<Vec<_>>().join("\n\n");
+                let mut lines = source.split('\n').map(|line| match line {
+                    "" => ">".to_string(),
+                    line => format!("> {line}"),
+                }).collect::<Vec<_>>();
+                match (attribution, cite) {
+                    (attribution, Some(cite)) => lines.push(format!("-- {}|{cite}", escape_section(attribution.as_deref().unwrap_or_default()))),
+                    (Some(attribution), None) => lines.push(format!("-- {}", escape_section(attribution))),
+                    (None, None) => {},
+                }
+                lines.join("\n")
+            },
+            SynElement::Table(table) => table.generate_lines(),
+            SynElement::Custom(custom) => custom.generate_line(),
+            SynElement::Math { source } => format!("$$\n{source}\n$$"),
+            SynElement::DefinitionList(definitions) => {
+                let line = |marker: &str, content: &[Inline]| match Self::escape_line_starts(&Inline::generate_line(content)).as_str() {
+                    "" => marker.to_string(),
+                    text => format!("{marker} {text}"),
+                };
+                let lines = definitions.iter().flat_map(|definition| {
+                    std::iter::once(line(";", &definition.term)).chain(definition.details.iter().map(|detail| line(":", detail)))
+                });
+                lines.collect::<Vec<_>>().join("\n")
+            },
+            SynElement::Footnote { id, content } => match Self::escape_line_starts(&Inline::generate_line(content)).as_str() {
+                "" => format!("[^{id}]:"),
+                text => format!("[^{id}]: {text}"),
+            },
+            SynElement::Callout { kind, title, children } => {
+                let marker = match Inline::generate_line(title).as_str() {
+                    "" => format!("[!{}]", kind.to_ascii_uppercase()),
+                    title => format!("[!{}] {title}", kind.to_ascii_uppercase()),
+                };
+                let lines = std::iter::once(marker).chain(children.iter().map(|child| child.generate_line())).collect::<Vec<_>>().join("\n\n");
+                lines.split('\n').map(|line| match line {
+                    "" => ">".to_string(),
+                    line => format!("> {line}"),
+                }).collect::<Vec<_>>().join("\n")
+            },
+            SynElement::Container { name, title, children } => {
+                let title = Inline::generate_line(title);
+                let mut lines = match title.as_str() {
+                    "" => vec![format!(":::{name}")],
+                    title => vec![format!(":::{name} {title}")],
+                };
+                if !children.is_empty() {
+                    lines.push(children.iter().map(|child| child.generate_line()).collect::<Vec<_>>().join("\n\n"));
+                }
+                lines.push(":::".into());
+                lines.join("\n")
+            },
+        }
+    }
+}
+
+
+impl Table {
+    /// Parses a block of lines starting with `|`. A missing or mismatched alignment row is an
+    /// error; rows with the wrong number of cells are padded or cut to fit, with a warning.
+    fn parse(lines: &[(usize, &str)], reporter: &mut Reporter) -> Result<Self, SynError> {
+        let header = Self::split_cells(lines[0].1);
+        let alignments = lines.get(1).and_then(|(_, line)| {
+            Self::split_cells(line).iter().map(|cell| match (cell.starts_with(':'), cell.trim_matches(':'), cell.ends_with(':')) {
+                (_, dashes, _) if dashes.is_empty() || !dashes.chars().all(|c| c == '-') => None,
+                (false, _, false) => Some(Alignment::Default),
+                (true, _, false) => Some(Alignment::Left),
+                (true, _, true) => Some(Alignment::Center),
+                (false, _, true) => Some(Alignment::Right),
+            }).collect::<Option<Vec<_>>>()
+        });
+
+        let (line_no, line) = lines.get(1).unwrap_or(&lines[0]);
+        let (mut alignments, body) = match alignments {
+            Some(alignments) => (alignments, &lines[2..]),
+            None => {
+                let error = SynError::MalformedTable { path: None, line: *line_no, column: 1, message: "expected a row like `| --- | --- |` under the header".into() };
+                let suggestion = header.iter().map(|_| " --- |").collect::<String>();
+                reporter.error(error, Span::line(*line_no, 1, line), Some(format!("|{suggestion}")))?;
+                (Vec::new(), &lines[1..])
+            },
+        };
+        if !alignments.is_empty() && alignments.len() != header.len() {
+            let message = format!("the alignment row has {} cells but the header has {}", alignments.len(), header.len());
+            let error = SynError::MalformedTable { path: None, line: *line_no, column: 1, message };
+            reporter.error(error, Span::line(*line_no, 1, line), None)?;
+        }
+        alignments.resize(header.len(), Alignment::Default);
+
+        let mut rows = Vec::new();
+        for (line_no, line) in body {
+            let mut row = Self::split_cells(line);
+            if row.len() != header.len() {
+                reporter.warning(Span::line(*line_no, 1, line), format!("row has {} cells but the header has {}", row.len(), header.len()), None);
+                row.resize(header.len(), String::new());
+            }
+            rows.push(row.iter().map(|cell| Inline::parse(cell)).collect());
+        }
+
+        Ok(Table { header: header.iter().map(|cell| Inline::parse(cell)).collect(), alignments, rows })
+    }
+
+
+    /// Splits a row on the `|`s that aren't escaped, unescapes the rest and trims each cell. The
+    /// `|`s at either end of the row are optional.
+    fn split_cells(line: &str) -> Vec<String> {
+        let line = line.trim();
+        let line = line.strip_prefix('|').unwrap_or(line);
+        let mut cells = vec![String::new()];
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'|') => {
+                    cells.last_mut().unwrap().push('|');
+                    chars.next();
+                },
+                '|' => cells.push(String::new()),
+                c => cells.last_mut().unwrap().push(c),
+            }
+        }
+        if cells.len() > 1 && cells.last().is_some_and(|cell| cell.trim().is_empty()) {
+            cells.pop();
+        }
+        cells.iter().map(|cell| cell.trim().to_string()).collect()
+    }
+
+
+    fn render(&self, context: &mut RenderContext) -> String {
+        let mut row = |cells: &[Vec<Inline>], tag: &str| {
+            let cells = cells.iter().zip(&self.alignments).map(|(cell, alignment)| {
+                let style = match alignment {
+                    Alignment::Default => "",
+                    Alignment::Left => " style='text-align: left'",
+                    Alignment::Center => " style='text-align: center'",
+                    Alignment::Right => " style='text-align: right'",
+                };
+                format!("<{tag}{style}>{}</{tag}>", Inline::render(cell, context))
+            });
+            format!("<tr>{}</tr>", cells.collect::<String>())
+        };
+
+        let header = row(&self.header, "th");
+        let body = match self.rows.is_empty() {
+            true => String::new(),
+            false => format!("<tbody>{}</tbody>", self.rows.iter().map(|cells| row(cells, "td")).collect::<String>()),
+        };
+        format!("<table><thead>{header}</thead>{body}</table>")
+    }
+
+
+    /// Checks that the table has a column, and an alignment and cell in every row for each one.
+    fn validate(&self) -> Result<(), String> {
+        let columns = self.header.len();
+        if columns == 0 {
+            return Err("has no columns".into());
+        }
+        if self.alignments.len() != columns {
+            return Err(format!("has {} alignments for {columns} columns", self.alignments.len()));
+        }
+        match self.rows.iter().find(|row| row.len() != columns) {
+            Some(row) => Err(format!("has a row of {} cells for {columns} columns", row.len())),
+            None => Ok(()),
+        }
+    }
+
+
+    fn generate_lines(&self) -> String {
+        let row = |cells: &[String]| format!("|{}", cells.iter().map(|cell| format!(" {cell} |")).collect::<String>());
+        let cells = |cells: &[Vec<Inline>]| cells.iter().map(|cell| Inline::generate_line(cell).replace('|', "\\|")).collect::<Vec<_>>();
+
+        let alignments = self.alignments.iter().map(|alignment| match alignment {
+            Alignment::Default => "---",
+            Alignment::Left => ":--",
+            Alignment::Center => ":-:",
+            Alignment::Right => "--:",
+        }.to_string()).collect::<Vec<_>>();
+
+        let mut lines = vec![row(&cells(&self.header)), row(&alignments)];
+        lines.extend(self.rows.iter().map(|cells_in_row| row(&cells(cells_in_row))));
+        lines.join("\n")
+    }
+}
+
+
+impl Media {
+    /// The named arguments of `.video` and `.audio` lines.
+    pub(crate) const KEYS: &'static [&'static str] = &["source", "poster", "track", "autoplay", "loop", "muted"];
+
+
+    /// Reads the arguments of a `.video` or `.audio` line. They should have been validated first.
+    pub(crate) fn from_args(kind: MediaKind, args: &DirectiveArgs) -> Self {
+        let named = |key: &'static str| args.named.iter().filter(move |(k, _)| k == key).map(|(_, value)| value.clone());
+        let flag = |key: &str| args.get_named(key) == Some("true");
+        Self {
+            kind,
+            sources: args.get(0).map(String::from).into_iter().chain(named("source")).collect(),
+            poster: args.get_named("poster").map(String::from),
+            tracks: named("track").map(|track| Track::parse(&track)).collect(),
+            autoplay: flag("autoplay"),
+            looping: flag("loop"),
+            muted: flag("muted"),
+            fallback: args.get(1).unwrap_or_default().to_string(),
+        }
+    }
+
+
+    pub(crate) fn to_args(&self) -> DirectiveArgs {
+        let mut positional = vec![self.sources.first().cloned().unwrap_or_default()];
+        if !self.fallback.is_empty() {
+            positional.push(self.fallback.clone());
+        }
+        let mut named = self.sources.iter().skip(1).map(|source| ("source".to_string(), source.clone())).collect::<Vec<_>>();
+        named.extend(self.poster.iter().map(|poster| ("poster".to_string(), poster.clone())));
+        named.extend(self.tracks.iter().map(|track| ("track".to_string(), track.generate())));
+        for (flag, key) in [(self.autoplay, "autoplay"), (self.looping, "loop"), (self.muted, "muted")] {
+            if flag {
+                named.push((key.to_string(), "true".to_string()));
+            }
+        }
+        DirectiveArgs { positional, named }
+    }
+
+
+    fn render(&self, options: &RenderOptions) -> String {
+        let url = |path: &str| match options.trusted_html {
+            true => escape_html(path),
+            false => escape_html(&sanitize_url(path)),
+        };
+        let tag = self.kind.name();
+
+        let mut attributes = " controls preload='metadata'".to_string();
+        if let Some(poster) = &self.poster {
+            attributes.push_str(&format!(" poster='{}'", url(poster)));
+        }
+        for (flag, name) in [(self.autoplay, "autoplay"), (self.looping, "loop"), (self.muted, "muted")] {
+            if flag {
+                attributes.push(' ');
+                attributes.push_str(name);
+            }
+        }
+
+        let sources = self.sources.iter().map(|path| match self.source_type(path) {
+            Some(media_type) => format!("<source src='{}' type='{media_type}'>", url(path)),
+            None => format!("<source src='{}'>", url(path)),
+        });
+        // The first track is shown by default, so captions are on for those who need them.
+        let tracks = self.tracks.iter().enumerate().map(|(i, track)| {
+            let label = if track.label.is_empty() { &track.lang } else { &track.label };
+            let default = if i == 0 { " default" } else { "" };
+            format!("<track kind='captions' src='{}' srclang='{}' label='{}'{default}>", url(&track.path), escape_html(&track.lang), escape_html(label))
+        });
+
+        let fallback = match (self.fallback.trim(), options.trusted_html) {
+            ("", _) => format!("Your browser can't play this {tag}."),
+            (fallback, true) => fallback.to_string(),
+            (fallback, false) => escape_html(fallback),
+        };
+        let download = match self.sources.first() {
+            Some(path) => format!(" <a href='{}'>Download the {tag}</a>.", url(path)),
+            None => String::new(),
+        };
+        format!("<{tag}{attributes}>{}{}<p>{fallback}{download}</p></{tag}>", sources.collect::<String>(), tracks.collect::<String>())
+    }
+
+
+    /// The MIME type of a source, guessed from its extension.
+    fn source_type(&self, path: &str) -> Option<&'static str> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        let video = self.kind == MediaKind::Video;
+        Some(match extension.as_str() {
+            "webm" if video => "video/webm",
+            "webm" => "audio/webm",
+            "ogg" if video => "video/ogg",
+            "ogv" => "video/ogg",
+            "ogg" | "oga" | "opus" => "audio/ogg",
+            "mp4" if video => "video/mp4",
+            "m4v" => "video/mp4",
+            "mp4" | "m4a" => "audio/mp4",
+            "mov" => "video/quicktime",
+            "mp3" => "audio/mpeg",
+            "wav" => "audio/wav",
+            "flac" => "audio/flac",
+            _ => return None,
+        })
+    }
+}
+
+
+impl MediaKind {
+    /// The directive and HTML tag name.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            MediaKind::Video => "video",
+            MediaKind::Audio => "audio",
+        }
+    }
+}
+
+
+impl Track {
+    /// Reads a `path.vtt;lang;label` track section. The label may be left out.
+    pub(crate) fn parse(source: &str) -> Self {
+        let mut parts = source.splitn(3, ';').map(String::from);
+        Self { path: parts.next().unwrap_or_default(), lang: parts.next().unwrap_or_default(), label: parts.next().unwrap_or_default() }
+    }
+
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !self.path.to_ascii_lowercase().ends_with(".vtt") {
+            return Err(format!("track '{}' isn't a WebVTT (.vtt) file", self.path));
+        }
+        if self.lang.trim().is_empty() {
+            return Err(format!("track '{}' has no language, as in `track={};en`", self.path, self.path));
+        }
+        Ok(())
+    }
+
+
+    fn generate(&self) -> String {
+        match self.label.as_str() {
+            "" => format!("{};{}", self.path, self.lang),
+            label => format!("{};{};{label}", self.path, self.lang),
+        }
+    }
+}
+
+
+impl Definition {
+    /// Reads the start of a `; term` or `: definition` line, returning whether it's a term, and
+    /// the text after the marker.
+    fn parse_marker(line: &str) -> Option<(bool, &str)> {
+        let line = line.trim();
+        let (term, text) = match line.strip_prefix(';') {
+            Some(text) => (true, text),
+            None => (false, line.strip_prefix(':')?),
+        };
+        (text.is_empty() || text.starts_with(' ')).then(|| (term, text.trim_start()))
+    }
+
+
+    /// Parses a block of lines starting with a term.
+    fn parse(lines: &[(usize, &str)]) -> Vec<Self> {
+        let mut sources: Vec<(String, Vec<String>)> = Vec::new();
+        for (_, line) in lines {
+            match Self::parse_marker(line) {
+                Some((true, text)) => sources.push((text.to_string(), Vec::new())),
+                Some((false, text)) => sources.last_mut().expect("definition lists start with a term").1.push(text.to_string()),
+                None => {
+                    let (term, details) = sources.last_mut().expect("definition lists start with a term");
+                    let source = details.last_mut().unwrap_or(term);
+                    source.push('\n');
+                    source.push_str(line.trim());
+                },
+            }
+        }
+        sources.iter().map(|(term, details)| Definition {
+            term: Inline::parse(term),
+            details: details.iter().map(|detail| Inline::parse(detail)).collect(),
+        }).collect()
+    }
+}
+
+
+impl List {
+    /// Merges nested lists of the same kind that follow each other, since they'd read back as one,
+    /// and drops empty ones.
+    fn normalize(&mut self) {
+        for item in &mut self.items {
+            let mut children: Vec<List> = Vec::new();
+            for mut child in std::mem::take(&mut item.children) {
+                child.normalize();
+                match children.last_mut() {
+                    _ if child.items.is_empty() => {},
+                    Some(last) if last.start.is_some() == child.start.is_some() => last.items.extend(child.items),
+                    _ => children.push(child),
+                }
+            }
+            item.children = children;
+        }
+    }
+
+
+    /// The inline content of every item, including those in nested lists.
+    fn content_mut(&mut self) -> Vec<&mut Vec<Inline>> {
+        let mut content = Vec::new();
+        for item in &mut self.items {
+            content.push(&mut item.content);
+            for child in &mut item.children {
+                content.extend(child.content_mut());
+            }
+        }
+        content
+    }
+
+
+    /// Reads a list marker: `- `, `* ` or a number and a `.`, followed by an optional task box.
+    fn parse_marker(line: &str) -> Option<ListMarker<'_>> {
+        let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+        let rest = line.trim();
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let (number, text) = match rest.strip_prefix(['-', '*']) {
+            Some(text) => (None, text),
+            None => (Some(rest[..digits].parse::<u64>().ok()?), rest[digits..].strip_prefix('.')?),
+        };
+        if !text.is_empty() && !text.starts_with(' ') {
+            return None;
+        }
+
+        let text = text.trim_start();
+        let checked = match text.get(..3) {
+            Some("[ ]") => Some(false),
+            Some("[x]" | "[X]") => Some(true),
+            _ => None,
+        };
+        match checked {
+            Some(_) if text.len() == 3 || text[3..].starts_with(' ') => Some(ListMarker { indent, number, checked, text: text[3..].trim_start() }),
+            _ => Some(ListMarker { indent, number, checked: None, text }),
+        }
+    }
+
+
+    /// Parses the list whose first item is at `lines[*pos]`, leaving `pos` after its last line. The
+    /// list ends at an item indented less than it, or one of another kind. Past the depth limit,
+    /// nested items are read as more text of the item they're in, markers and all.
+    fn parse(lines: &[(usize, &str)], pos: &mut usize, reporter: &mut Reporter) -> Result<Self, SynError> {
+        let first = Self::parse_marker(lines[*pos].1).expect("lists start with an item");
+        let mut list = List { start: first.number, items: Vec::new() };
+
+        while let Some(marker) = lines.get(*pos).and_then(|(_, line)| Self::parse_marker(line)) {
+            if marker.indent != first.indent || marker.number.is_some() != first.number.is_some() {
+                break;
+            }
+            *pos += 1;
+
+            let mut source = marker.text.to_string();
+            let mut children = Vec::new();
+            loop {
+                while let Some((_, line)) = lines.get(*pos).filter(|(_, line)| Self::parse_marker(line).is_none()) {
+                    source.push('\n');
+                    source.push_str(line.trim());
+                    *pos += 1;
+                }
+                let Some(child) = lines.get(*pos).and_then(|(_, line)| Self::parse_marker(line)).filter(|child| child.indent > first.indent) else {
+                    break;
+                };
+                if reporter.depth >= reporter.options.max_container_depth {
+                    let (line_no, line) = lines[*pos];
+                    let message = format!("lists are nested more than {} deep", reporter.options.max_container_depth);
+                    let error = SynError::MalformedList { path: None, line: line_no, column: child.indent + 1, message };
+                    reporter.error(error, Span::line(line_no, child.indent + 1, line), None)?;
+                    source.push('\n');
+                    source.push_str(line.trim());
+                    *pos += 1;
+                    continue;
+                }
+
+                reporter.depth += 1;
+                let child = Self::parse(lines, pos, reporter);
+                reporter.depth -= 1;
+                children.push(child?);
+            }
+            list.items.push(ListItem { checked: marker.checked, content: Inline::parse(&source), children });
+        }
+        Ok(list)
+    }
+
+
+    fn render(&self, context: &mut RenderContext) -> String {
+        let items = self.items.iter().map(|item| {
+            let content = Inline::render(&item.content, context);
+            let children = item.children.iter().map(|child| child.render(context)).collect::<String>();
+            match item.checked {
+                Some(checked) => {
+                    let checked = if checked { " checked" } else { "" };
+                    format!("<li class='task'><input type='checkbox' disabled{checked}> {content}{children}</li>")
+                },
+                None => format!("<li>{content}{children}</li>"),
+            }
+        }).collect::<String>();
+
+        match self.start {
+            None => format!("<ul>{items}</ul>"),
+            Some(1) => format!("<ol>{items}</ol>"),
+            Some(start) => format!("<ol start='{start}'>{items}</ol>"),
+        }
+    }
+
+
+    /// Writes out the list's lines, with its markers at `indent` and nested lists lined up with
+    /// the content of the item they're in.
+    fn generate_lines(&self, indent: usize, lines: &mut Vec<String>) {
+        for (n, item) in self.items.iter().enumerate() {
+            let mut marker = match self.start {
+                Some(start) => format!("{}.", start.saturating_add(n as u64)),
+                None => "-".into(),
+            };
+            match item.checked {
+                Some(true) => marker.push_str(" [x]"),
+                Some(false) => marker.push_str(" [ ]"),
+                None => {},
+            }
+
+            let content_indent = " ".repeat(indent + marker.split(' ').next().unwrap_or_default().len() + 1);
+            let source = SynElement::escape_line_starts(&Inline::generate_line(&item.content));
+            for (i, line) in source.split('\n').enumerate() {
+                lines.push(match (i, line.is_empty()) {
+                    (0, true) => format!("{}{marker}", " ".repeat(indent)),
+                    (0, false) => format!("{}{marker} {line}", " ".repeat(indent)),
+                    _ => format!("{content_indent}{line}"),
+                });
+            }
+            for child in &item.children {
+                child.generate_lines(content_indent.len(), lines);
+            }
+        }
+    }
+}
+
+
+impl SynFile {
+    /// Makes a file from its parts, put in the form loading it back would give them. The header
+    /// fields have newlines replaced with spaces and are trimmed, and tags are split at commas and
+    /// trimmed in turn, with none left if they come to an empty line. The elements are
+    /// normalized, with their inline content as `Inline::normalize` describes, their directive
+    /// sections on one trimmed line, and empty paragraphs and lists dropped. Elements that still
+    /// couldn't be saved so they load back the same, like math we can't convert or a footnote
+    /// that's never defined, are an error.
+    pub fn new(title: String, tags: Vec<String>, posted: String, summary: String, mut elements: Vec<SynElement>) -> Result<Self, SynError> {
+        let line = |field: String| field.replace('\n', " ").trim().to_string();
+        let tags = Self::split_tags(&line(tags.join(",")));
+        SynElement::normalize(&mut elements);
+        SynElement::validate(&elements, &Directives::default())?;
+        SynElement::validate_footnotes(&elements)?;
+        Ok(Self { title: line(title), tags, posted: line(posted), summary: line(summary), metadata: Metadata::default(), elements })
+    }
+
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, SynError> {
+        Self::load_file_with(path, &ParseOptions::default()).map(|(file, _)| file)
+    }
+
+
+    /// Loads a file, returning any warnings along with it. With `options.recover` set, errors
+    /// other than failing to read the file are returned as diagnostics as well.
+    pub fn load_file_with<P: AsRef<Path>>(path: P, options: &ParseOptions) -> Result<(Self, Vec<Diagnostic>), SynError> {
+        let path = path.as_ref();
+        let in_file = File::open(path).map_err(|e| SynError::io(path, e))?;
+        Self::parse(BufReader::new(in_file), Some(path), options)
+    }
+
+
+    pub fn load_file_metadata<P: AsRef<Path>>(path: P) -> Result<Self, SynError> {
+        let path = path.as_ref();
+        let in_file = File::open(path).map_err(|e| SynError::io(path, e))?;
+        let options = ParseOptions::default();
+        let mut reporter = Reporter::new(Some(path), &options);
+        Self::read_header(&mut LineReader::new(BufReader::new(in_file)), &mut reporter)
+    }
+
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, SynError> {
+        Self::from_reader_with(reader, &ParseOptions::default()).map(|(file, _)| file)
+    }
+
+
+    pub fn from_reader_with<R: BufRead>(reader: R, options: &ParseOptions) -> Result<(Self, Vec<Diagnostic>), SynError> {
+        Self::parse(reader, None, options)
+    }
+
+
+    /// Reads only the header, like `load_file_metadata`, stopping before the elements.
+    pub fn metadata_from_reader<R: BufRead>(reader: R) -> Result<Self, SynError> {
+        let options = ParseOptions::default();
+        Self::read_header(&mut LineReader::new(reader), &mut Reporter::new(None, &options))
+    }
+
+
+    pub fn from_str_with(source: &str, options: &ParseOptions) -> Result<(Self, Vec<Diagnostic>), SynError> {
+        Self::parse(source.as_bytes(), None, options)
+    }
+
+
+    fn parse<R: BufRead>(reader: R, path: Option<&Path>, options: &ParseOptions) -> Result<(Self, Vec<Diagnostic>), SynError> {
+        let mut reader = LineReader::new(reader);
+        let mut reporter = Reporter::new(path, options);
+        let mut file = Self::read_header(&mut reader, &mut reporter)?;
+
+        let mut lines = Vec::new();
+        while let Some(line) = reader.read_line(&mut reporter)? {
+            lines.push((reader.line, line));
+        }
+        let lines = lines.iter().map(|(line_no, line)| (*line_no, line.trim_end_matches(['\n', '\r']))).collect::<Vec<_>>();
+        file.elements = SynElement::parse_lines(&lines, &mut reporter)?;
+        Self::check_footnotes(&lines, &mut reporter)?;
+
+        Ok((file, reporter.diagnostics))
+    }
+
+
+    /// Checks that every footnote referenced is defined exactly once, and every one defined is
+    /// referenced. Problems are reported in line order.
+    fn check_footnotes(lines: &[(usize, &str)], reporter: &mut Reporter) -> Result<(), SynError> {
+        let refs = std::mem::take(&mut reporter.footnote_refs);
+        let definitions = std::mem::take(&mut reporter.footnote_definitions);
+        let referenced = refs.iter().map(|(id, _)| id.as_str()).collect::<HashSet<_>>();
+        let defined = definitions.iter().map(|(id, _)| id.as_str()).collect::<HashSet<_>>();
+        let mut problems = Vec::new();
+        let mut seen = HashSet::new();
+        for (id, line_no) in &definitions {
+            if !seen.insert(id.as_str()) {
+                problems.push((*line_no, id, "is defined more than once"));
+            } else if !referenced.contains(id.as_str()) {
+                problems.push((*line_no, id, "is never referenced"));
+            }
+        }
+        let mut seen = HashSet::new();
+        for (id, line_no) in &refs {
+            if seen.insert(id.as_str()) && !defined.contains(id.as_str()) {
+                problems.push((*line_no, id, "is never defined"));
+            }
+        }
+        problems.sort_by_key(|(line_no, _, _)| *line_no);
+
+        for (line_no, id, message) in problems {
+            let line = lines.binary_search_by_key(&line_no, |(other, _)| *other).map_or("", |i| lines[i].1);
+            let column = line.chars().take_while(|c| c.is_whitespace()).count() + 1;
+            let error = SynError::InvalidFootnote { path: None, line: line_no, column, id: id.clone(), message: message.into() };
+            reporter.error(error, Span::line(line_no, column, line), None)?;
+        }
+        Ok(())
+    }
+
+
+    fn split_tags(line: &str) -> Vec<String> {
+        // An empty tag line means no tags, rather than a single empty one.
+        match line.trim() {
+            "" => Vec::new(),
+            tags => tags.split(",").map(|e| e.trim().to_string()).collect::<Vec<_>>(),
+        }
+    }
+
+
+    /// Reads the four fixed header lines and any metadata, leaving the reader positioned at the
+    /// first element.
+    fn read_header<R: BufRead>(reader: &mut LineReader<R>, reporter: &mut Reporter) -> Result<Self, SynError> {
+        let title = reader.read_header_line(HeaderField::Title, reporter)?.trim().to_string();
+        let tags = Self::split_tags(&reader.read_header_line(HeaderField::Tags, reporter)?);
+        let posted = reader.read_header_line(HeaderField::Posted, reporter)?.trim().to_string();
+        let summary = reader.read_header_line(HeaderField::Summary, reporter)?.trim().to_string();
+        let metadata = Self::read_metadata(reader, reporter)?;
+
+        Ok(Self {
+            title,
+            tags,
+            posted,
+            summary,
+            metadata,
+            elements: Vec::new(),
+        })
+    }
+
+
+    /// Reads the `---` block of metadata straight after the summary, if there is one. Otherwise
+    /// the lines are put back to be read as elements. That includes a block that isn't closed, or
+    /// has a line that isn't `key: value`, since before there was metadata that was a rule and
+    /// some text.
+    fn read_metadata<R: BufRead>(reader: &mut LineReader<R>, reporter: &mut Reporter) -> Result<Metadata, SynError> {
+        let mut block = Vec::new();
+        match reader.read_line(reporter)? {
+            Some(line) if line.trim() == "---" => block.push(line),
+            Some(line) => {
+                reader.unread(vec![line]);
+                return Ok(Metadata::default());
+            },
+            None => return Ok(Metadata::default()),
+        }
+
+        loop {
+            let Some(line) = reader.read_line(reporter)? else {
+                reader.unread(block);
+                return Ok(Metadata::default());
+            };
+            let closed = line.trim() == "---";
+            let malformed = !closed && !line.trim().is_empty() && Metadata::split_line(&line).is_none();
+            block.push(line);
+            if malformed {
+                reader.unread(block);
+                return Ok(Metadata::default());
+            } else if closed {
+                break;
+            }
+        }
+
+        let mut metadata = Metadata::default();
+        let first_line = reader.line + 1 - block.len();
+        for (i, line) in block[1..block.len() - 1].iter().enumerate() {
+            let (line_no, line) = (first_line + 1 + i, line.trim_end_matches(['\n', '\r']));
+            // Every other line was checked to be `key: value` while reading the block.
+            let Some((key, value)) = Metadata::split_line(line) else {
+                continue;
+            };
+
+            let problem = match MetadataValue::parse(value.trim()) {
+                _ if metadata.get(key).is_some() => Some((1, format!("`{key}` is set more than once"))),
+                Ok(value) => {
+                    metadata.insert(key.to_string(), value);
+                    None
+                },
+                Err(message) => Some((line.chars().count() - value.trim_start().chars().count() + 1, message)),
+            };
+            if let Some((column, message)) = problem {
+                let error = SynError::MalformedMetadata { path: None, line: line_no, column, message };
+                reporter.error(error, Span::line(line_no, column, line), None)?;
+            }
+        }
+        Ok(metadata)
+    }
+
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), SynError> {
+        let path = path.as_ref();
+        let out_file = File::create(path).map_err(|e| SynError::io(path, e))?;
+        let mut out_file = BufWriter::new(out_file);
+        write!(out_file, "{self}")
+            .and_then(|_| out_file.flush())
+            .map_err(|e| SynError::io(path, e))
+    }
+
+
+    /// Writes the file in the same format as `save_file`.
+    pub fn write_to<W: Write>(&self, mut out: W) -> Result<(), SynError> {
+        write!(out, "{self}").map_err(|e| SynError::Io { path: None, source: e })
+    }
+
+
+    /// Renders every element in order. Heading IDs are unique across the whole document.
+    pub fn generate_html(&self) -> String {
+        self.generate_html_with(&RenderOptions::default())
+    }
+
+
+    pub fn generate_html_with(&self, options: &RenderOptions) -> String {
+        fn collect_footnotes<'a>(elements: &'a [SynElement], context: &mut RenderContext<'a>) {
+            for element in elements {
+                if let SynElement::Footnote { id, content } = element {
+                    context.footnotes.entry(id).or_insert(content);
+                }
+                collect_footnotes(element.children(), context);
+            }
+        }
+
+        fn collect_glossary(elements: &[SynElement], glossary: &mut HashMap<String, String>) {
+            for element in elements {
+                if let SynElement::DefinitionList(definitions) = element {
+                    for definition in definitions {
+                        let term = Inline::plain_text(&definition.term);
+                        if let (false, Some(detail)) = (term.trim().is_empty(), definition.details.first()) {
+                            glossary.insert(term.trim().to_string(), Inline::plain_text(detail));
+                        }
+                    }
+                }
+                collect_glossary(element.children(), glossary);
+            }
+        }
+
+        let mut context = RenderContext::new(options);
+        collect_footnotes(&self.elements, &mut context);
+        if !options.trusted_html {
+            let mut glossary = options.glossary.clone();
+            collect_glossary(&self.elements, &mut glossary);
+            context.glossary = glossary.into_iter().filter(|(term, _)| !term.is_empty()).collect();
+            context.glossary.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+        }
+        let html = SynElement::render_all(&self.elements, &mut context);
+        match context.footnote_section() {
+            section if section.is_empty() => html,
+            section => format!("{html}\n{section}"),
+        }
+    }
+
+
+    pub fn get_title(&self) -> &String {
+        &self.title
+    }
+    pub fn get_tags(&self) -> &Vec<String> {
+        &self.tags
+    }
+    pub fn get_posted(&self) -> &String {
+        &self.posted
+    }
+    pub fn get_summary(&self) -> &String {
+        &self.summary
+    }
+    pub fn get_metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+    pub fn get_metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+    pub fn get_elements(&self) -> &Vec<SynElement> {
+        &self.elements
+    }
+}
+
+
+
+impl FromStr for SynFile {
+    type Err = SynError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Self::from_str_with(source, &ParseOptions::default()).map(|(file, _)| file)
+    }
+}
+
+
+/// Formats the file in its source form, exactly as `save_file` would write it.
+impl fmt::Display for SynFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}\n{}\n{}\n", self.title, self.tags.join(","), self.posted, self.summary)?;
+        if !self.metadata.is_empty() {
+            writeln!(f, "---")?;
+            for (key, value) in self.metadata.iter() {
+                writeln!(f, "{key}: {value}")?;
+            }
+            writeln!(f, "---")?;
+        }
+        writeln!(f)?;
+        for element in &self.elements {
+            write!(f, "{}\n\n", element.generate_line())?;
+        }
+        Ok(())
+    }
+}
+
+
+impl Metadata {
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.entries.iter().find(|(other, _)| other == key).map(|(_, value)| value)
+    }
+
+
+    /// Sets `key`, keeping its place if it's already set and adding it at the end otherwise.
+    /// Returns the value it had before. `key` should be a lowercase identifier like `cover-image`,
+    /// or the file won't read back the same.
+    pub fn insert(&mut self, key: String, value: MetadataValue) -> Option<MetadataValue> {
+        match self.entries.iter_mut().find(|(other, _)| *other == key) {
+            Some((_, old)) => Some(std::mem::replace(old, value)),
+            None => {
+                self.entries.push((key, value));
+                None
+            },
+        }
+    }
+
+
+    pub fn remove(&mut self, key: &str) -> Option<MetadataValue> {
+        let index = self.entries.iter().position(|(other, _)| other == key)?;
+        Some(self.entries.remove(index).1)
+    }
+
+
+    /// The entries in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MetadataValue)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+
+    /// Splits a `key: value` line. The colon comes straight after the key, and a space or the end
+    /// of the line after it.
+    fn split_line(line: &str) -> Option<(&str, &str)> {
+        let (key, value) = line.split_once(':')?;
+        let spaced = value.is_empty() || value.starts_with([' ', '\t']);
+        (is_identifier(key) && spaced).then_some((key, value))
+    }
+}
+
+
+impl MetadataValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MetadataValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            MetadataValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            MetadataValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+
+    /// Reads a value as written after `key:`, without the surrounding whitespace. Only integers
+    /// written the way `i64` formats them count, so `007` stays text and is written back as is.
+    fn parse(value: &str) -> Result<Self, String> {
+        if let Some(quoted) = value.strip_prefix('"') {
+            let mut text = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' if chars.as_str().is_empty() => return Ok(MetadataValue::Text(text)),
+                    '"' => return Err("text after the closing quote".into()),
+                    '\\' => match chars.next() {
+                        Some(c @ ('"' | '\\')) => text.push(c),
+                        Some('n') => text.push('\n'),
+                        Some('r') => text.push('\r'),
+                        Some(c) => return Err(format!("unknown escape `\\{c}`")),
+                        None => break,
+                    },
+                    c => text.push(c),
+                }
+            }
+            return Err("quoted text is never closed".into());
+        }
+
+        match value {
+            "true" => Ok(MetadataValue::Bool(true)),
+            "false" => Ok(MetadataValue::Bool(false)),
+            _ => match value.parse::<i64>() {
+                Ok(integer) if integer.to_string() == value => Ok(MetadataValue::Integer(integer)),
+                _ => Ok(MetadataValue::Text(value.to_string())),
+            },
+        }
+    }
+}
+
+
+/// Formats the value as it's written in a file.
+impl fmt::Display for MetadataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataValue::Bool(value) => write!(f, "{value}"),
+            MetadataValue::Integer(value) => write!(f, "{value}"),
+            MetadataValue::Text(text) => {
+                let plain = !text.is_empty() && text.trim() == text && !text.contains(['\n', '\r']) && Self::parse(text).as_ref() == Ok(self);
+                if plain {
+                    return write!(f, "{text}");
+                }
+                f.write_str("\"")?;
+                for c in text.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                f.write_str("\"")
+            },
+        }
+    }
+}
+
+
+
+// HTML helpers
+
+/// Escapes text for use in HTML text nodes and quoted attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+/// Where `word` first appears in `text` as a whole word, not as part of a longer one.
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    text.match_indices(word).map(|(at, _)| at).find(|at| {
+        let before = text[..*at].chars().next_back();
+        let after = text[at + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+
+fn image_tag(path: &str, alt: &str, style: &str, options: &RenderOptions) -> String {
+    let (path, style) = match options.trusted_html {
+        true => (path.to_string(), style.to_string()),
+        false => (sanitize_url(path), sanitize_style(style)),
+    };
+    let style = match style.trim() {
+        "" => String::new(),
+        style => format!(" style='{}'", escape_html(style)),
+    };
+    format!("<img src='{}' alt='{}'{style}>", escape_html(&path), escape_html(alt))
+}
+
+
+/// Replaces URLs with a scheme that could run script (`javascript:` and friends) with `#`.
+/// Relative URLs and the usual web schemes pass through unchanged.
+fn sanitize_url(url: &str) -> String {
+    const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+    // Browsers ignore whitespace and control characters inside the scheme, so we do too.
+    let compact = url.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect::<String>();
+    let scheme_end = compact.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')));
+    match scheme_end {
+        Some(end) if compact[end..].starts_with(':') && end > 0 => {
+            match ALLOWED_SCHEMES.contains(&compact[..end].to_ascii_lowercase().as_str()) {
+                true => url.to_string(),
+                false => "#".into(),
+            }
+        },
+        _ => url.to_string(),
+    }
+}
+
+
+/// Drops style declarations that can load resources or run script. Declarations with a backslash
+/// are dropped too, since a CSS escape like `u\72l(` can spell out any of them.
+fn sanitize_style(style: &str) -> String {
+    const FORBIDDEN: &[&str] = &["url(", "expression(", "javascript:", "@import", "behavior", "-moz-binding"];
+
+    style
+        .split(';')
+        .filter(|declaration| {
+            let compact = declaration.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_lowercase();
+            !compact.contains('\\') && !FORBIDDEN.iter().any(|forbidden| compact.contains(forbidden))
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small deterministic xorshift generator, so property tests need no extra dependencies.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len())]
+        }
+    }
+
+    /// Arbitrary bytes, biased towards the pieces of syntax the parser cares about.
+    fn arbitrary_bytes(rng: &mut Rng) -> Vec<u8> {
+        const PIECES: &[&[u8]] = &[b"\n", b"\r\n", b"|", b"#", b"---", b".img ", b" ", b"a", b",", b"\xff", b"\xc3", b"\xc3\xa9", b"\xe2\x82", b"key: ", b"\"", b">", b":::", b"$", b"{", b"_**"];
+        let mut bytes = Vec::new();
+        for _ in 0..rng.below(64) {
+            if rng.below(4) == 0 {
+                bytes.push(rng.next() as u8);
+            } else {
+                bytes.extend_from_slice(rng.pick(PIECES));
+            }
+        }
+        bytes
+    }
+
+    fn arbitrary_words(rng: &mut Rng, separators: &[&str]) -> String {
+        const WORDS: &[&str] = &[
+            "lorem", "ipsum", "Hello,", "SynBlog!", "100%", "x.img", "mid#hash", "---x", "caf\u{e9}", "\u{1f980}",
+            "#tag", "---", ".img", ".figure", "\\", "a\\", "\\\\b", "a*b", "**", "snake_case", "[x]", "[^x]:", "`t`", "(y)", "$5", ":", "a|b",
+        ];
+        let mut text = rng.pick(WORDS).to_string();
+        for _ in 0..rng.below(6) {
+            text.push_str(rng.pick(separators));
+            text.push_str(rng.pick(WORDS));
+        }
+        text
+    }
+
+    /// Arbitrary inline content, including what `SynFile::new` has to normalize or reject: spans
+    /// directly inside spans of their own kind, links inside links, adjacent and empty code spans,
+    /// line breaks where the content has to stay on one line, footnote references that are
+    /// malformed or inside links, and math that's padded, empty, can't be converted or can't be
+    /// written between `$`s, or is followed by a digit.
+    fn arbitrary_inlines(rng: &mut Rng, depth: usize) -> Vec<Inline> {
+        let mut content = Vec::new();
+        for i in 0..rng.below(4) + 1 {
+            if i > 0 && rng.below(2) == 0 {
+                content.push(Inline::Text(" ".into()));
+            }
+            content.push(match rng.below(if depth > 0 { 9 } else { 4 }) {
+                0 => Inline::Text(arbitrary_words(rng, &[" ", " ", "\n"])),
+                1 => Inline::Text(format!(" {} ", arbitrary_words(rng, &[" "]))),
+                2 => Inline::LineBreak,
+                3 => Inline::Code(match rng.below(4) {
+                    0 => rng.pick(&["", " ", "`"]).into(),
+                    1 => format!(" {} ", arbitrary_words(rng, &["`"])),
+                    _ => arbitrary_words(rng, &[" ", "`", "``", "\n"]),
+                }),
+                4 => Inline::Emphasis(arbitrary_inlines(rng, depth - 1)),
+                5 => Inline::Strong(arbitrary_inlines(rng, depth - 1)),
+                6 => Inline::Link { href: arbitrary_words(rng, &["/", " ", "\n"]), content: arbitrary_inlines(rng, depth - 1) },
+                // References are kept rare, as any inside a link is an error.
+                7 if rng.below(4) > 0 => Inline::Text(arbitrary_words(rng, &[" "])),
+                7 => Inline::FootnoteRef(match rng.below(8) {
+                    0 => "bad id".into(),
+                    _ => rng.pick(&["1", "note", "a-b"]).into(),
+                }),
+                _ => Inline::Math(match rng.below(12) {
+                    0 => rng.pick(&[" x ", "", "\n", "a$b", "x\\", "\\unknown"]),
+                    _ => rng.pick(&["x^2", "\\frac{a}{b}", "|x| \\$", "e^{i\\pi} = -1", "a\\,"]),
+                }.into()),
+            });
+        }
+        content
+    }
+
+    /// A post with a fixed header, and `body` as the elements after it.
+    pub(crate) fn post(body: &str) -> String {
+        format!("Title\ntag\n2024-01-01\nSummary\n\n{body}")
+    }
+
+    fn parse(body: &str, options: &ParseOptions) -> Result<(SynFile, Vec<Diagnostic>), SynError> {
+        SynFile::from_str_with(&post(body), options)
+    }
+
+    /// The message of the error from parsing `body` with the default options.
+    fn parse_error(body: &str) -> String {
+        SynFile::from_str(&post(body)).map(|_| ()).unwrap_err().message()
+    }
+
+    fn recover() -> ParseOptions {
+        ParseOptions { recover: true, ..ParseOptions::default() }
+    }
+
+    fn paragraph(text: &str) -> SynElement {
+        let mut content = Vec::new();
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                content.push(Inline::LineBreak);
+            }
+            if !line.is_empty() {
+                content.push(Inline::Text(line.into()));
+            }
+        }
+        SynElement::Text(content)
+    }
+
+    fn heading(level: u8, text: &str) -> SynElement {
+        SynElement::Heading { level, content: vec![Inline::Text(text.into())] }
+    }
+
+    /// A list, possibly empty, whose nested lists may be empty or follow one of the same kind.
+    fn arbitrary_list(rng: &mut Rng, depth: usize) -> List {
+        let items = (0..rng.below(4)).map(|_| ListItem {
+            checked: rng.pick(&[None, Some(false), Some(true)]),
+            content: arbitrary_inlines(rng, 2),
+            children: match depth {
+                0 => Vec::new(),
+                _ => (0..rng.below(3)).map(|_| arbitrary_list(rng, depth - 1)).collect(),
+            },
+        }).collect();
+        List { start: rng.pick(&[None, Some(())]).map(|_| rng.below(12) as u64), items }
+    }
+
+    fn arbitrary_element(rng: &mut Rng) -> SynElement {
+        match rng.below(15) {
+            0 => SynElement::Text(match rng.below(8) {
+                0 => Vec::new(),
+                _ => arbitrary_inlines(rng, 3),
+            }),
+            1 => SynElement::Heading { level: rng.below(8) as u8, content: arbitrary_inlines(rng, 3) },
+            2 => SynElement::Image { path: arbitrary_words(rng, &["/"]), alt: arbitrary_words(rng, &[" ", "\n"]), style: arbitrary_words(rng, &[";"]) + rng.pick(&["", " "]) },
+            3 => SynElement::Figure {
+                path: arbitrary_words(rng, &["/"]),
+                alt: arbitrary_words(rng, &[" "]),
+                style: arbitrary_words(rng, &[";"]) + rng.pick(&["", " "]),
+                caption: arbitrary_words(rng, &[" "]),
+                credit: match rng.below(2) {
+                    0 => Some(arbitrary_words(rng, &[" "])),
+                    _ => None,
+                },
+            },
+            4 => SynElement::LineH,
+            5 => SynElement::List(arbitrary_list(rng, 2)),
+            6 => SynElement::Quote {
+                children: (0..rng.below(3) + 1).map(|_| arbitrary_element(rng)).collect(),
+                attribution: match rng.below(4) {
+                    0 => None,
+                    1 => Some(rng.pick(&["", " Ada ", "Ada\nLovelace"]).into()),
+                    _ => Some(arbitrary_words(rng, &[" "])),
+                },
+                cite: match rng.below(3) {
+                    0 => None,
+                    _ => Some(arbitrary_words(rng, &["/", "\n"])),
+                },
+            },
+            7 => {
+                let columns = match rng.below(16) {
+                    0 => 0,
+                    n => n % 3 + 1,
+                };
+                let row_count = rng.below(3);
+                // Now and then the first row has a cell too many.
+                let extra = usize::from(rng.below(8) == 0);
+                let alignments = (0..columns).map(|_| rng.pick(&[Alignment::Default, Alignment::Left, Alignment::Center, Alignment::Right])).collect();
+                let mut row = |columns| (0..columns).map(|_| match rng.below(4) {
+                    0 => Vec::new(),
+                    _ => arbitrary_inlines(rng, 2),
+                }).collect::<Vec<_>>();
+                let header = row(columns);
+                let rows = (0..row_count).map(|i| row(if i == 0 { columns + extra } else { columns })).collect();
+                SynElement::Table(Table { header, alignments, rows })
+            },
+            8 => SynElement::Container {
+                name: match rng.below(16) {
+                    0 => rng.pick(&["Bad Name", ""]).into(),
+                    _ => rng.pick(&["note", "details", "two-columns"]).into(),
+                },
+                title: match rng.below(2) {
+                    0 => Vec::new(),
+                    _ => arbitrary_inlines(rng, 1),
+                },
+                children: (0..rng.below(3)).map(|_| arbitrary_element(rng)).collect(),
+            },
+            9 => SynElement::Callout {
+                kind: match rng.below(16) {
+                    0 => rng.pick(&["bad kind", ""]).into(),
+                    _ => rng.pick(&["note", "warning", "bug-report", "NOTE", "Tip"]).into(),
+                },
+                title: match rng.below(2) {
+                    0 => Vec::new(),
+                    _ => arbitrary_inlines(rng, 1),
+                },
+                children: (0..rng.below(3)).map(|_| arbitrary_element(rng)).collect(),
+            },
+            10 => {
+                const LINES: &[&str] = &["", "  x^2", "\\sum_{n=0}^{N-1} x_n", "\\$ \\{", "a \\quad b", "x^2\r"];
+                let mut lines = (0..rng.below(4)).map(|_| rng.pick(LINES)).collect::<Vec<_>>();
+                if rng.below(8) == 0 {
+                    lines.push(rng.pick(&[" $$ ", "\\unknown"]));
+                }
+                SynElement::Math { source: lines.join("\n") }
+            },
+            11 => {
+                let kind = rng.pick(&[MediaKind::Video, MediaKind::Audio]);
+                let tracks = (0..rng.below(3)).map(|_| Track {
+                    path: format!("{}.vtt", match rng.below(16) {
+                        0 => arbitrary_words(rng, &[";"]),
+                        _ => arbitrary_words(rng, &["/", " "]),
+                    }),
+                    lang: rng.pick(&["en", "de-CH"]).into(),
+                    label: rng.pick(&["", "English", "Deutsch (Schweiz)"]).into(),
+                }).collect();
+                SynElement::Media(Media {
+                    kind,
+                    sources: (0..rng.below(3) + usize::from(rng.below(8) > 0)).map(|_| arbitrary_words(rng, &["/", ".", " "])).collect(),
+                    poster: rng.pick(&[None, Some(())]).filter(|_| kind == MediaKind::Video || rng.below(8) == 0).map(|_| arbitrary_words(rng, &["/", " \""])),
+                    tracks,
+                    autoplay: rng.below(2) == 0,
+                    looping: rng.below(2) == 0,
+                    muted: rng.below(2) == 0,
+                    fallback: rng.pick(&["", "Your browser can't play this.", "source=x", "Get it loop=true", "a \\\\muted=\"x y\""]).into(),
+                })
+            },
+            12 => SynElement::Embed {
+                provider: rng.pick(&["youtube", "mastodon", "peertube"]).into(),
+                id: match rng.below(8) {
+                    0 => rng.pick(&["a b", ""]).into(),
+                    _ => rng.pick(&["dQw4w9WgXcQ", "mastodon.social/@user/1234"]).into(),
+                },
+                thumbnail: rng.pick(&["", "thumbs/a.jpg"]).into(),
+                title: rng.pick(&[None, Some(())]).map(|_| arbitrary_words(rng, &[" "])).unwrap_or_default(),
+            },
+            13 => SynElement::DefinitionList((0..rng.below(3) + 1).map(|_| Definition {
+                term: arbitrary_inlines(rng, 2),
+                details: (0..rng.below(3)).map(|_| arbitrary_inlines(rng, 2)).collect(),
+            }).collect()),
+            _ => {
+                const LINES: &[&str] = &["", "    indented", "\tfn main() {}", "```", " ```` ", "```rust", "#heading", ".img a|b|c", "---", "a \\ b", "crlf\r", "$$"];
+                let source = (0..rng.below(5)).map(|_| rng.pick(LINES)).collect::<Vec<_>>().join("\n");
+                SynElement::Code { lang: match rng.below(16) {
+                    0 => "a`b".into(),
+                    _ => rng.pick(&["", "rust", "python ignore", "rust ", " two\nlines"]).into(),
+                }, source }
+            },
+        }
+    }
+
+    fn arbitrary_file(rng: &mut Rng) -> Result<SynFile, SynError> {
+        let tags = (0..rng.below(4)).map(|_| match rng.below(4) {
+            0 => String::new(),
+            _ => arbitrary_words(rng, &[" ", ",", "\n"]),
+        }).collect();
+        let mut elements = (0..rng.below(12)).map(|_| arbitrary_element(rng)).collect::<Vec<_>>();
+        // Every footnote referenced has to be defined, once, and that includes the ones referenced
+        // by footnotes. Now and then one is left undefined or defined twice.
+        let mut defined = Vec::new();
+        loop {
+            let mut refs = Vec::new();
+            collect_footnote_refs(&elements, &mut refs);
+            refs.retain(|id| !defined.contains(id));
+            if refs.is_empty() {
+                break;
+            }
+            for id in refs {
+                for _ in 0..rng.pick(&[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 2]) {
+                    elements.push(SynElement::Footnote { id: id.clone(), content: arbitrary_inlines(rng, 2) });
+                }
+                defined.push(id);
+            }
+        }
+        let mut file = SynFile::new(arbitrary_words(rng, &[" ", "\n"]), tags, "2024-01-01".into(), arbitrary_words(rng, &[" ", "\n"]), elements)?;
+        for _ in 0..rng.below(4) {
+            let key = rng.pick(&["author", "draft", "cover-image", "order", "x_1"]).to_string();
+            let value = match rng.below(4) {
+                0 => MetadataValue::Bool(rng.below(2) == 0),
+                1 => MetadataValue::Integer(rng.next() as i64),
+                2 => MetadataValue::Text(rng.pick(&["", "true", "-12", "007", " padded ", "\"quoted\"", "a\\n", "two\nlines", "k: v"]).into()),
+                _ => MetadataValue::Text(arbitrary_words(rng, &[" ", "\t", "\n"])),
+            };
+            file.get_metadata_mut().insert(key, value);
+        }
+        Ok(file)
+    }
+
+    fn collect_footnote_refs(elements: &[SynElement], ids: &mut Vec<String>) {
+        for element in elements {
+            for content in element.inline_content() {
+                Inline::visit(content, &mut |inline| match inline {
+                    Inline::FootnoteRef(id) if !ids.contains(id) => ids.push(id.clone()),
+                    _ => {},
+                });
+            }
+            collect_footnote_refs(element.children(), ids);
+        }
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("syn-blog-format-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn text_element() {
+        let element = SynElement::parse_line("Hello,\\\nSynBlog!".into()).unwrap();
+        assert_eq!(element, paragraph("Hello,\nSynBlog!"));
+
+        let as_line = element.generate_line();
+        assert_eq!(as_line, "Hello,\\\nSynBlog!".to_string());
+
+        let as_tag = element.generate_tag();
+        assert_eq!(as_tag, "<p>Hello,<br>SynBlog!</p>");
+    }
+
+    #[test]
+    fn img_element() {
+        let element = SynElement::parse_line(".img test.png|A test image!|width:100%".into()).unwrap();
+        assert_eq!(element, SynElement::Image { path: "test.png".into(), alt: "A test image!".into(), style: "width:100%".into() });
+
+        let as_line = element.generate_line();
+        assert_eq!(as_line, ".img test.png|A test image!|width:100%".to_string());
+
+        let as_tag = element.generate_tag();
+        assert_eq!(as_tag, "<img src='test.png' alt='A test image!' style='width:100%'>");
+    }
+
+    #[test]
+    fn figure_element() {
+        let element = SynElement::parse_line(".figure cat.jpg|A cat|width:50%|Our cat, Miso.|Photo by me".into()).unwrap();
+        assert_eq!(element, SynElement::Figure {
+            path: "cat.jpg".into(),
+            alt: "A cat".into(),
+            style: "width:50%".into(),
+            caption: "Our cat, Miso.".into(),
+            credit: Some("Photo by me".into()),
+        });
+
+        let as_line = element.generate_line();
+        assert_eq!(as_line, ".figure cat.jpg|A cat|width:50%|Our cat, Miso.|Photo by me".to_string());
+
+        let as_tag = element.generate_tag();
+        assert_eq!(as_tag, "<figure><img src='cat.jpg' alt='A cat' style='width:50%'><figcaption>Our cat, Miso. <small class='credit'>Photo by me</small></figcaption></figure>");
+
+        let element = SynElement::parse_line(".figure cat.jpg|A cat||Miso.".into()).unwrap();
+        assert_eq!(element.generate_tag(), "<figure><img src='cat.jpg' alt='A cat'><figcaption>Miso.</figcaption></figure>");
+        assert!(SynElement::parse_line(".figure cat.jpg|A cat|".into()).is_err());
+    }
+
+    #[test]
+    fn heading_element() {
+        let element = SynElement::parse_line("#Big Title".into()).unwrap();
+        assert_eq!(element, heading(1, "Big Title"));
+
+        let as_line = element.generate_line();
+        assert_eq!(as_line, "#Big Title".to_string());
+
+        let as_tag = element.generate_tag();
+        assert_eq!(as_tag, "<h2 id='big-title'>Big Title</h2>");
+    }
+
+    #[test]
+    fn lineh_element() {
+        let element = SynElement::parse_line("---".into()).unwrap();
+        assert_eq!(element, SynElement::LineH);
+
+        let as_line = element.generate_line();
+        assert_eq!(as_line, "---".to_string());
+
+        let as_tag = element.generate_tag();
+        assert_eq!(as_tag, "<div class='hline'></div>");
+    }
+
+    #[test]
+    fn malformed_img_error() {
+        let err = SynElement::parse_line("  .img test.png|only alt".into()).unwrap_err();
+        assert!(matches!(err, SynError::MalformedDirective { line: 1, column: 8, .. }));
+
+        let path = temp_file("malformed.txt", post(".img a.png|alt\n").as_bytes());
+        let err = SynFile::load_file(&path).unwrap_err();
+        assert_eq!(err.path(), Some(path.as_path()));
+        assert_eq!((err.line(), err.column()), (Some(6), Some(6)));
+    }
+
+    #[test]
+    fn missing_header_error() {
+        let path = temp_file("truncated.txt", b"Title\ntag\n");
+        let err = SynFile::load_file_metadata(&path).unwrap_err();
+        assert!(matches!(err, SynError::MissingHeader { field: HeaderField::Posted, line: 3, .. }));
+    }
+
+    #[test]
+    fn invalid_utf8_error() {
+        let path = temp_file("utf8.txt", b"Title\ntag\n2024-01-01\nSumm\xffary\n");
+        let err = SynFile::load_file(&path).unwrap_err();
+        assert!(matches!(err, SynError::InvalidUtf8 { line: 4, column: 5, .. }));
+        assert!(err.to_string().ends_with(":4:5: invalid UTF-8"));
+    }
+
+    #[test]
+    fn missing_file_error() {
+        let err = SynFile::load_file("/nonexistent/post.txt").unwrap_err();
+        assert!(matches!(err, SynError::Io { .. }));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn save_file_error() {
+        let file = SynFile::load_file_metadata(temp_file("save.txt", b"Title\ntag\n2024-01-01\nSummary\n")).unwrap();
+        let err = file.save_file("/nonexistent/dir/post.txt").unwrap_err();
+        assert!(matches!(err, SynError::Io { .. }));
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic() {
+        let mut rng = Rng(0x5eed_1234_abcd_ef01);
+        let path = std::env::temp_dir().join(format!("syn-blog-format-{}-fuzz.txt", std::process::id()));
+        for _ in 0..2000 {
+            let bytes = arbitrary_bytes(&mut rng);
+            std::fs::write(&path, &bytes).unwrap();
+            let line_count = bytes.split(|b| *b == b'\n').count() - usize::from(bytes.ends_with(b"\n") || bytes.is_empty());
+
+            let full = SynFile::load_file(&path);
+            let metadata = SynFile::load_file_metadata(&path);
+            for result in [full.as_ref().err(), metadata.as_ref().err()].into_iter().flatten() {
+                assert!(result.line().unwrap() <= line_count + 1, "{result} for {bytes:?}");
+            }
+
+            if let Ok(text) = std::str::from_utf8(&bytes) {
+                if line_count < 4 {
+                    assert!(matches!(metadata, Err(SynError::MissingHeader { line, .. }) if line == line_count + 1), "{text:?}");
+                } else {
+                    assert!(matches!(metadata, Ok(_) | Err(SynError::MalformedMetadata { .. } | SynError::UnclosedBlock { .. })), "{text:?}");
+                }
+            } else {
+                assert!(full.is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn recovering_parse_collects_diagnostics() {
+        let path = temp_file("recover.txt", post(".img a.png|alt\n\nText\n\n.img |x|y|z\n").as_bytes());
+        let (file, diagnostics) = SynFile::load_file_with(&path, &recover()).unwrap();
+
+        assert!(file.get_elements().contains(&SynElement::Image { path: "a.png".into(), alt: "alt".into(), style: "".into() }));
+        assert!(file.get_elements().contains(&SynElement::Image { path: "".into(), alt: "x".into(), style: "y|z".into() }));
+        assert!(file.get_elements().contains(&paragraph("Text")));
+
+        let severities = diagnostics.iter().map(|d| (d.severity, d.span.line)).collect::<Vec<_>>();
+        assert_eq!(severities, vec![(Severity::Error, 6), (Severity::Error, 10), (Severity::Warning, 10)]);
+        assert_eq!(diagnostics[0].span, Span { line: 6, column: 6, end_line: 6, end_column: 15 });
+        assert_eq!(diagnostics[0].suggestion.as_deref(), Some("a.png|alt|"));
+    }
+
+    #[test]
+    fn recovering_parse_of_truncated_file() {
+        let path = temp_file("recover-truncated.txt", b"Ti\xfftle\ntag\n");
+        let (file, diagnostics) = SynFile::load_file_with(&path, &recover()).unwrap();
+        assert_eq!(file.get_title(), "Ti\u{fffd}tle");
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+    }
+
+    #[test]
+    fn parse_from_str_and_reader() {
+        let source = "Title\ntag one, tag two\n2024-01-01\nSummary\n\n#Heading\n\n.img a.png|alt|\n";
+        let file = SynFile::from_str(source).unwrap();
+        assert_eq!(file.get_tags(), &vec!["tag one".to_string(), "tag two".to_string()]);
+        assert!(file.get_elements().contains(&heading(1, "Heading")));
+
+        let from_reader = SynFile::from_reader(io::Cursor::new(source)).unwrap();
+        assert_eq!(from_reader.get_elements(), file.get_elements());
+
+        let metadata = SynFile::metadata_from_reader(source.as_bytes()).unwrap();
+        assert_eq!(metadata.get_summary(), "Summary");
+        assert!(metadata.get_elements().is_empty());
+
+        let err = "Title\n.img x".parse::<SynFile>().unwrap_err();
+        assert!(matches!(err, SynError::MissingHeader { path: None, field: HeaderField::Posted, .. }));
+    }
+
+    #[test]
+    fn write_to_matches_save_file() {
+        let file = SynFile::from_str(&post("#Heading\n")).unwrap();
+        let path = temp_file("write-to.txt", b"");
+        file.save_file(&path).unwrap();
+
+        let mut written = Vec::new();
+        file.write_to(&mut written).unwrap();
+        assert_eq!(written, std::fs::read(&path).unwrap());
+        assert_eq!(file.to_string().as_bytes(), written.as_slice());
+    }
+
+    #[test]
+    fn multi_line_paragraphs() {
+        let file = SynFile::from_str(&post("A paragraph\n  wrapped over\nthree lines.\n\nSecond\\\nparagraph\n#Heading\nThird\n")).unwrap();
+        assert_eq!(file.get_elements(), &vec![
+            paragraph("A paragraph wrapped over three lines."),
+            paragraph("Second\nparagraph"),
+            heading(1, "Heading"),
+            paragraph("Third"),
+        ]);
+        assert_eq!(file.get_elements()[1].generate_tag(), "<p>Second<br>paragraph</p>");
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut rng = Rng(0x0dd_ba11_cafe_f00d);
+        let path = temp_file("round-trip.txt", b"");
+        let mut saved = 0;
+        for _ in 0..1000 {
+            // Elements `SynFile::new` rejects are fine, as long as it doesn't accept any that
+            // wouldn't load back the same.
+            let Ok(file) = arbitrary_file(&mut rng) else { continue };
+            file.save_file(&path).unwrap();
+            assert_eq!(SynFile::load_file(&path).unwrap(), file, "{file}");
+            saved += 1;
+        }
+        assert!(saved > 350, "only {saved} files were valid");
+    }
+
+    #[test]
+    fn new_normalizes_to_the_loaded_form() {
+        let elements = vec![
+            SynElement::Text(vec![]),
+            SynElement::Text(vec![Inline::Text(" a ".into()), Inline::LineBreak, Inline::Text("  b|c".into())]),
+            SynElement::Heading { level: 2, content: vec![Inline::Text("two\nlines".into())] },
+            SynElement::Quote { children: vec![SynElement::Text(vec![Inline::Text(" ".into())])], attribution: None, cite: None },
+        ];
+        let file = SynFile::new("A\ntitle ".into(), vec!["".into()], "2024-01-01".into(), "Summary".into(), elements).unwrap();
+        assert_eq!(file.get_title(), "A title");
+        assert!(file.get_tags().is_empty());
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::Text(vec![Inline::Text("a ".into()), Inline::LineBreak, Inline::Text("b|c".into())]),
+            heading(2, "two lines"),
+            SynElement::Quote { children: vec![], attribution: None, cite: None },
+        ]);
+        assert_eq!(SynFile::from_str(&file.to_string()).unwrap(), file);
+
+        let file = SynFile::new("Title".into(), vec![" a, b".into(), "c\nd".into()], "2024-01-01".into(), "Summary".into(), vec![]).unwrap();
+        assert_eq!(file.get_tags(), &vec!["a".to_string(), "b".to_string(), "c d".to_string()]);
+    }
+
+    #[test]
+    fn new_normalizes_what_would_read_back_differently() {
+        let text = |text: &str| Inline::Text(text.into());
+        let item = |content: &str, children| ListItem { checked: None, content: vec![text(content)], children };
+        let elements = vec![
+            SynElement::Heading { level: 0, content: vec![text("a"), Inline::LineBreak, Inline::Emphasis(vec![Inline::LineBreak, text("b")])] },
+            SynElement::Heading { level: 7, content: vec![text("c")] },
+            SynElement::Text(vec![
+                Inline::Emphasis(vec![Inline::Emphasis(vec![text("x")])]), text(" "), Inline::Strong(vec![text("y"), Inline::Strong(vec![text("z")])]),
+                text(" "), Inline::Code(String::new()), Inline::Math(" x ".into()), text(" "), Inline::Code("a".into()), Inline::Code("b".into()),
+                Inline::Link { href: "a\nb".into(), content: vec![Inline::Link { href: "c".into(), content: vec![text("d")] }] },
+            ]),
+            SynElement::Table(Table { header: vec![vec![text("a"), Inline::LineBreak, text("b")]], alignments: vec![Alignment::Default], rows: vec![] }),
+            SynElement::Quote { children: vec![], attribution: Some(" Ada ".into()), cite: Some("a\nb".into()) },
+            SynElement::Quote { children: vec![], attribution: Some(String::new()), cite: None },
+            SynElement::Image { path: "a.png".into(), alt: String::new(), style: "c ".into() },
+            SynElement::Code { lang: "rust ".into(), source: "x\r\ny".into() },
+            SynElement::Callout { kind: "NOTE".into(), title: vec![], children: vec![] },
+            SynElement::List(List { start: None, items: vec![item("a", vec![
+                List { start: None, items: vec![item("b", vec![])] },
+                List { start: None, items: vec![] },
+                List { start: None, items: vec![item("c", vec![])] },
+            ])] }),
+            SynElement::List(List { start: Some(1), items: vec![] }),
+        ];
+        let file = SynFile::new("Title".into(), vec![], "2024-01-01".into(), "Summary".into(), elements).unwrap();
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::Heading { level: 1, content: vec![text("a "), Inline::Emphasis(vec![text(" b")])] },
+            heading(6, "c"),
+            SynElement::Text(vec![
+                Inline::Emphasis(vec![text("x")]), text(" "), Inline::Strong(vec![text("yz")]),
+                text(" "), Inline::Math("x".into()), text(" "), Inline::Code("ab".into()),
+                Inline::Link { href: "a b".into(), content: vec![text("d")] },
+            ]),
+            SynElement::Table(Table { header: vec![vec![text("a b")]], alignments: vec![Alignment::Default], rows: vec![] }),
+            SynElement::Quote { children: vec![], attribution: Some("Ada".into()), cite: Some("a b".into()) },
+            SynElement::Quote { children: vec![], attribution: None, cite: None },
+            SynElement::Image { path: "a.png".into(), alt: String::new(), style: "c".into() },
+            SynElement::Code { lang: "rust".into(), source: "x\ny".into() },
+            SynElement::Callout { kind: "note".into(), title: vec![], children: vec![] },
+            SynElement::List(List { start: None, items: vec![item("a", vec![List { start: None, items: vec![item("b", vec![]), item("c", vec![])] }])] }),
+        ]);
+        assert_eq!(SynFile::from_str(&file.to_string()).unwrap(), file);
+    }
+
+    #[test]
+    fn new_rejects_what_cant_load_back() {
+        let error = |elements: Vec<SynElement>| SynFile::new("Title".into(), vec![], "2024-01-01".into(), "Summary".into(), elements).unwrap_err().message();
+        let text = |content: Vec<Inline>| vec![SynElement::Text(content)];
+        assert_eq!(error(vec![SynElement::Math { source: "a\n$$".into() }]), "invalid element: math block has a `$$` line, which would close it");
+        assert_eq!(error(vec![SynElement::Math { source: "\\unknown".into() }]), "invalid element: malformed math: unsupported command `\\unknown`");
+        assert_eq!(error(vec![SynElement::Code { lang: "a`b".into(), source: String::new() }]), "invalid element: code language 'a`b' has a backtick, which would end the fence");
+        assert_eq!(error(vec![SynElement::Container { name: "Bad Name".into(), title: vec![], children: vec![] }]), "invalid element: 'Bad Name' isn't a container name");
+        assert_eq!(error(vec![SynElement::Callout { kind: "bad kind".into(), title: vec![], children: vec![] }]), "invalid element: 'bad kind' isn't a callout kind");
+        assert_eq!(error(vec![SynElement::Table(Table { header: vec![], alignments: vec![], rows: vec![] })]), "invalid element: table has no columns");
+        let table = Table { header: vec![vec![]], alignments: vec![Alignment::Default], rows: vec![vec![vec![], vec![]]] };
+        assert_eq!(error(vec![SynElement::Table(table)]), "invalid element: table has a row of 2 cells for 1 columns");
+        assert_eq!(error(text(vec![Inline::Math("a$b".into())])), "invalid element: `a$b` can't be written as inline math");
+        assert_eq!(error(text(vec![Inline::Math("x".into()), Inline::Text("2".into())])), "invalid element: inline math `x` can't be followed by a digit, as in `2`");
+        let link = Inline::Link { href: "a".into(), content: vec![Inline::FootnoteRef("1".into())] };
+        assert_eq!(error(text(vec![link])), "invalid element: footnote reference [^1] can't be inside a link");
+        assert_eq!(error(text(vec![Inline::FootnoteRef("1".into())])), "invalid element: footnote [^1] is never defined");
+        assert_eq!(error(vec![SynElement::Embed { provider: "youtube".into(), id: "a b".into(), thumbnail: String::new(), title: String::new() }]), "invalid element: .embed directive 'a b' isn't a valid ID");
+    }
+
+    #[test]
+    fn reloading_is_stable() {
+        let mut rng = Rng(0xfeed_face_dead_beef);
+        for _ in 0..2000 {
+            let bytes = arbitrary_bytes(&mut rng);
+            if let Ok(file) = SynFile::from_reader(bytes.as_slice()) {
+                let saved = file.to_string();
+                let reloaded = SynFile::from_str(&saved).unwrap();
+                assert_eq!(reloaded, file, "{bytes:?}");
+                assert_eq!(reloaded.to_string(), saved);
+            }
+        }
+    }
+
+    #[test]
+    fn blank_lines_are_not_paragraphs() {
+        let source = "Title\n\n2024-01-01\nSummary\n\n\nText\n\n\n\n#Heading\n\n";
+        let file = SynFile::from_str(source).unwrap();
+        assert!(file.get_tags().is_empty());
+        assert_eq!(file.get_elements(), &vec![paragraph("Text"), heading(1, "Heading")]);
+        assert_eq!(SynFile::from_str(&file.to_string()).unwrap().to_string(), file.to_string());
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let text = paragraph("<script>alert('hi')</script> & more");
+        assert_eq!(text.generate_tag(), "<p>&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt; &amp; more</p>");
+
+        let heading = heading(1, "Fish & <b>Chips</b>");
+        assert_eq!(heading.generate_tag(), "<h2 id='fish-bchipsb'>Fish &amp; &lt;b&gt;Chips&lt;/b&gt;</h2>");
+
+        let image = SynElement::Image { path: "it's.png".into(), alt: "<alt>".into(), style: "color:red' onload='x".into() };
+        assert_eq!(image.generate_tag(), "<img src='it&#39;s.png' alt='&lt;alt&gt;' style='color:red&#39; onload=&#39;x'>");
+    }
+
+    #[test]
+    fn urls_and_styles_are_sanitized() {
+        assert_eq!(sanitize_url("images/a.png"), "images/a.png");
+        assert_eq!(sanitize_url("https://example.com/a.png"), "https://example.com/a.png");
+        assert_eq!(sanitize_url("java\tscript:alert(1)"), "#");
+        assert_eq!(sanitize_url(" JavaScript:alert(1)"), "#");
+        assert_eq!(sanitize_url("data:text/html,x"), "#");
+        assert_eq!(sanitize_style("width:100%;background:url(x.png);color:red"), "width:100%;color:red");
+        assert_eq!(sanitize_style("color:red;background:u\\72l(https://tracker.example/x.gif)"), "color:red");
+        assert_eq!(sanitize_style("background:\\75rl(x.png);width:1px"), "width:1px");
+    }
+
+    #[test]
+    fn trusted_html_passes_through() {
+        let options = RenderOptions { trusted_html: true, ..Default::default() };
+        let text = paragraph("<em>hi</em>\nthere");
+        assert_eq!(text.generate_tag_with(&options), "<p><em>hi</em><br>there</p>");
+        // Markup characters inside tags are left alone, but still work between them and after a
+        // `<` that doesn't start a tag.
+        let text = SynElement::Text(Inline::parse("<a href=\"/foo_bar_baz\" class='a**b**' title='$x$ [y](z)'>_hi_</a>"));
+        assert_eq!(text.generate_tag_with(&options), "<p><a href=\"/foo_bar_baz\" class='a**b**' title='$x$ [y](z)'><em>hi</em></a></p>");
+        assert_eq!(SynElement::Text(Inline::parse("a <b _c_ and x_y_z <1 _d_")).generate_tag(), "<p>a &lt;b <em>c</em> and x<em>y</em>z &lt;1 <em>d</em></p>");
+
+        let image = SynElement::Image { path: "javascript:x".into(), alt: "a".into(), style: "".into() };
+        assert_eq!(image.generate_tag_with(&options), "<img src='javascript:x' alt='a'>");
+    }
+
+    #[test]
+    fn heading_levels_and_anchors() {
+        let file = SynFile::from_str(&post("#Intro\n\n##Setup\n\n###### Deep\n\n########Too deep\n\n##Setup\n\n#!!!\n")).unwrap();
+        let levels = file.get_elements().iter().map(|e| match e {
+            SynElement::Heading { level, content } => (*level, Inline::plain_text(content)),
+            _ => unreachable!(),
+        }).collect::<Vec<_>>();
+        assert_eq!(levels, vec![(1, "Intro".into()), (2, "Setup".into()), (6, "Deep".into()), (6, "##Too deep".into()), (2, "Setup".into()), (1, "!!!".into())]);
+
+        assert_eq!(file.generate_html(), [
+            "<h2 id='intro'>Intro</h2>",
+            "<h3 id='setup'>Setup</h3>",
+            "<h6 id='deep'>Deep</h6>",
+            "<h6 id='too-deep'>##Too deep</h6>",
+            "<h3 id='setup-1'>Setup</h3>",
+            "<h2 id='section'>!!!</h2>",
+        ].join("\n"));
+
+        let options = RenderOptions { heading_base_level: 1, ..Default::default() };
+        assert!(file.generate_html_with(&options).starts_with("<h1 id='intro'>Intro</h1>\n<h2 id='setup'>"));
+
+        let spaced = SynElement::Heading { level: 2, content: vec![Inline::Text("  two spaces".into())] };
+        assert_eq!(spaced.generate_line(), "##\\  two spaces");
+        assert_eq!(SynFile::from_str(&post("##\\  two spaces\n")).unwrap().get_elements(), &vec![spaced]);
+    }
+
+    #[test]
+    fn escaped_paragraph_lines() {
+        let file = SynFile::from_str(&post("\\#1 on the charts\n\n\\---\n\n\\.img not an image\n\n\\\\ one backslash\n\nends in a backslash\\\\\n")).unwrap();
+        assert_eq!(file.get_elements(), &vec![
+            paragraph("#1 on the charts"),
+            paragraph("---"),
+            paragraph(".img not an image"),
+            paragraph("\\ one backslash"),
+            paragraph("ends in a backslash\\"),
+        ]);
+
+        for element in file.get_elements() {
+            assert_eq!(&SynElement::parse_line(element.generate_line()).unwrap(), element);
+        }
+
+        let tricky = paragraph(" leading space\n#heading?\n\n\\\n");
+        assert_eq!(tricky.generate_line(), "\\ leading space\\\n\\#heading?\\\n\\\n\\\\\\");
+        assert_eq!(SynElement::parse_line(tricky.generate_line()).unwrap(), tricky);
+
+        let heading = heading(2, "#hashtag");
+        assert_eq!(heading.generate_line(), "##\\#hashtag");
+        assert_eq!(SynElement::parse_line(heading.generate_line()).unwrap(), heading);
+    }
+
+    #[test]
+    fn inline_markup_in_elements() {
+        let file = SynFile::from_str(&post("##Using `Vec<T>` **safely**\n\nSee [the _docs_](https://doc.rust-lang.org)\nfor more.\n")).unwrap();
+        let html = file.generate_html();
+        assert_eq!(html, "<h3 id='using-vect-safely'>Using <code>Vec&lt;T&gt;</code> <strong>safely</strong></h3>\n<p>See <a href='https://doc.rust-lang.org'>the <em>docs</em></a> for more.</p>");
+        assert_eq!(file.get_elements()[1].generate_line(), "See [the _docs_](https://doc.rust-lang.org) for more.");
+    }
+
+    #[test]
+    fn fenced_code_blocks() {
+        let source = "fn main() {\n    // <b> & .img a|b|c\n\n    println!(\"```\");\n}";
+        let file = SynFile::from_str(&post(&format!("```rust\n{source}\n```\n```\n```\n"))).unwrap();
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::Code { lang: "rust".into(), source: source.into() },
+            SynElement::Code { lang: "".into(), source: "".into() },
+        ]);
+        assert_eq!(file.generate_html(), "<pre><code class='language-rust'>fn main() {\n    // &lt;b&gt; &amp; .img a|b|c\n\n    println!(&quot;```&quot;);\n}</code></pre>\n<pre><code></code></pre>");
+
+        let element = SynElement::Code { lang: "text".into(), source: "```\n ```` ".into() };
+        assert_eq!(element.generate_line(), "`````text\n```\n ```` \n`````");
+        assert_eq!(SynElement::parse_line(element.generate_line()).unwrap(), element);
+
+        // A fence with a backtick after it is an inline code span, and text that looks like a
+        // fence gets escaped.
+        assert_eq!(SynElement::parse_line("```a``` b".into()).unwrap(), SynElement::Text(vec![Inline::Code("a".into()), Inline::Text(" b".into())]));
+        assert_eq!(SynElement::Text(vec![Inline::Text("```".into())]).generate_line(), "\\`\\`\\`");
+    }
+
+    #[test]
+    fn highlighted_code_blocks() {
+        let element = SynElement::Code { lang: "rust ignore".into(), source: "let x = 1;".into() };
+        let options = RenderOptions { highlighting: Highlighting::Classes, ..RenderOptions::default() };
+        assert_eq!(element.generate_tag_with(&options), "<pre><code class='language-rust'><span class='hl-keyword'>let</span> x = <span class='hl-number'>1</span>;</code></pre>");
+        assert_eq!(element.generate_tag(), "<pre><code class='language-rust'>let x = 1;</code></pre>");
+    }
+
+    #[test]
+    fn unclosed_code_fence() {
+        let source = post("  ````\nlet x = 1;\n```\n");
+        let err = SynFile::from_str(&source).unwrap_err();
+        assert!(matches!(err, SynError::UnclosedBlock { line: 6, column: 3, .. }));
+        assert_eq!(err.to_string(), "6:3: block opened with ```` is never closed");
+
+        let (file, diagnostics) = SynFile::from_str_with(&source, &recover()).unwrap();
+        assert_eq!(file.get_elements(), &vec![SynElement::Code { lang: "".into(), source: "let x = 1;\n```".into() }]);
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn nested_and_task_lists() {
+        let source = post("- one\n  wrapped\n- [x] two\n  3. three\n  4. [ ] four\n     - five\n  * six\n\n1. again\n");
+        let file = SynFile::from_str(&source).unwrap();
+        let item = |text: &str, checked, children| ListItem { checked, content: vec![Inline::Text(text.into())], children };
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::List(List { start: None, items: vec![
+                item("one wrapped", None, vec![]),
+                item("two", Some(true), vec![
+                    List { start: Some(3), items: vec![
+                        item("three", None, vec![]),
+                        item("four", Some(false), vec![List { start: None, items: vec![item("five", None, vec![])] }]),
+                    ] },
+                    List { start: None, items: vec![item("six", None, vec![])] },
+                ]),
+            ] }),
+            SynElement::List(List { start: Some(1), items: vec![item("again", None, vec![])] }),
+        ]);
+
+        assert_eq!(file.generate_html(), concat!(
+            "<ul><li>one wrapped</li><li class='task'><input type='checkbox' disabled checked> two",
+            "<ol start='3'><li>three</li><li class='task'><input type='checkbox' disabled> four<ul><li>five</li></ul></li></ol>",
+            "<ul><li>six</li></ul></li></ul>\n<ol><li>again</li></ol>",
+        ));
+        assert_eq!(file.get_elements()[0].generate_line(), "- one wrapped\n- [x] two\n  3. three\n  4. [ ] four\n     - five\n  - six");
+    }
+
+    #[test]
+    fn list_lookalikes_are_escaped() {
+        let element = SynElement::List(List { start: None, items: vec![ListItem {
+            checked: None,
+            content: vec![Inline::Text("[x] not done".into()), Inline::LineBreak, Inline::Text("- not an item".into())],
+            children: vec![],
+        }] });
+        assert_eq!(element.generate_line(), "- \\[x\\] not done\\\n  \\- not an item");
+        assert_eq!(SynElement::parse_line(element.generate_line()).unwrap(), element);
+
+        for text in ["- dash", "2. two", "*", "2024."] {
+            let element = paragraph(text);
+            assert_eq!(SynElement::parse_line(element.generate_line()).unwrap(), element);
+        }
+        assert_eq!(SynElement::parse_line("-not a list".into()).unwrap(), paragraph("-not a list"));
+    }
+
+    #[test]
+    fn block_quotes() {
+        let source = post("> Quoted _text_\n>\n> - a list\n> > nested\n> ```\n>   code\n> ```\n-- Ada Lovelace|https://example.com/notes\n\n>plain\n-- \n");
+        let file = SynFile::from_str(&source).unwrap();
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::Quote {
+                children: vec![
+                    SynElement::Text(vec![Inline::Text("Quoted ".into()), Inline::Emphasis(vec![Inline::Text("text".into())])]),
+                    SynElement::List(List { start: None, items: vec![ListItem { checked: None, content: vec![Inline::Text("a list".into())], children: vec![] }] }),
+                    SynElement::Quote { children: vec![paragraph("nested")], attribution: None, cite: None },
+                    SynElement::Code { lang: "".into(), source: "  code".into() },
+                ],
+                attribution: Some("Ada Lovelace".into()),
+                cite: Some("https://example.com/notes".into()),
+            },
+            SynElement::Quote { children: vec![paragraph("plain")], attribution: None, cite: None },
+        ]);
+
+        assert_eq!(file.generate_html(), concat!(
+            "<blockquote cite='https://example.com/notes'><p>Quoted <em>text</em></p>\n<ul><li>a list</li></ul>\n",
+            "<blockquote><p>nested</p></blockquote>\n<pre><code>  code</code></pre>",
+            "<footer>\u{2014} <cite><a href='https://example.com/notes'>Ada Lovelace</a></cite></footer></blockquote>\n",
+            "<blockquote><p>plain</p></blockquote>",
+        ));
+        assert_eq!(file.get_elements()[0].generate_line(), "> Quoted _text_\n>\n> - a list\n>\n> > nested\n>\n> ```\n>   code\n> ```\n-- Ada Lovelace|https://example.com/notes");
+
+        let unsafe_cite = SynElement::Quote { children: vec![paragraph("x")], attribution: Some("<Eve>".into()), cite: Some("javascript:alert(1)".into()) };
+        assert_eq!(unsafe_cite.generate_tag(), "<blockquote cite='#'><p>x</p><footer>\u{2014} <cite><a href='#'>&lt;Eve&gt;</a></cite></footer></blockquote>");
+        assert_eq!(paragraph("> not a quote").generate_line(), "\\> not a quote");
+
+        for cite in [None, Some("https://example.com/a|b".to_string())] {
+            let quote = SynElement::Quote { children: vec![paragraph("x")], attribution: Some("Smith | Jones".into()), cite: cite.clone() };
+            let line = quote.generate_line();
+            assert!(line.ends_with(&format!("-- Smith \\| Jones{}", cite.as_ref().map_or(String::new(), |cite| format!("|{cite}")))), "{line}");
+            let file = SynFile::new("Title".into(), vec![], "2024-01-01".into(), "Summary".into(), vec![quote.clone()]).unwrap();
+            assert_eq!(SynFile::from_str(&file.to_string()).unwrap().get_elements(), &vec![quote]);
+        }
+    }
+
+    #[test]
+    fn tables() {
+        let source = post("| Crate | Time `a\\|b` |  |\n|:---|---:|:-:|\n| **serde** | 1.5 ms | x |\n|short\n");
+        let (file, diagnostics) = SynFile::from_str_with(&source, &ParseOptions::default()).unwrap();
+        let cell = |text: &str| vec![Inline::Text(text.into())];
+        assert_eq!(file.get_elements(), &vec![SynElement::Table(Table {
+            header: vec![cell("Crate"), vec![Inline::Text("Time ".into()), Inline::Code("a|b".into())], vec![]],
+            alignments: vec![Alignment::Left, Alignment::Right, Alignment::Center],
+            rows: vec![
+                vec![vec![Inline::Strong(cell("serde"))], cell("1.5 ms"), cell("x")],
+                vec![cell("short"), vec![], vec![]],
+            ],
+        })]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.line, 9);
+
+        assert_eq!(file.generate_html(), concat!(
+            "<table><thead><tr><th style='text-align: left'>Crate</th><th style='text-align: right'>Time <code>a|b</code></th>",
+            "<th style='text-align: center'></th></tr></thead><tbody>",
+            "<tr><td style='text-align: left'><strong>serde</strong></td><td style='text-align: right'>1.5 ms</td><td style='text-align: center'>x</td></tr>",
+            "<tr><td style='text-align: left'>short</td><td style='text-align: right'></td><td style='text-align: center'></td></tr>",
+            "</tbody></table>",
+        ));
+        assert_eq!(file.get_elements()[0].generate_line(), "| Crate | Time `a\\|b` |  |\n| :-- | --: | :-: |\n| **serde** | 1.5 ms | x |\n| short |  |  |");
+    }
+
+    #[test]
+    fn table_without_alignment_row() {
+        let source = post("| a | b |\n| c | d |\n");
+        let err = SynFile::from_str(&source).unwrap_err();
+        assert!(matches!(err, SynError::MalformedTable { line: 7, column: 1, .. }));
+
+        let (file, diagnostics) = SynFile::from_str_with(&source, &recover()).unwrap();
+        assert_eq!(diagnostics[0].suggestion.as_deref(), Some("| --- | --- |"));
+        let SynElement::Table(table) = &file.get_elements()[0] else { panic!() };
+        assert_eq!(table.alignments, vec![Alignment::Default; 2]);
+        assert_eq!(table.rows.len(), 1);
+        assert_eq!(paragraph("| not a table").generate_line(), "\\| not a table");
+    }
+
+    #[test]
+    fn containers() {
+        let source = post(":::details Show **more**\nHidden\n:::columns\n:::column\n- left\n:::\n\n```\n:::\n```\n:::\n:::\n\n\\:::note\n");
+        let file = SynFile::from_str(&source).unwrap();
+        let container = |name: &str, title: Vec<Inline>, children| SynElement::Container { name: name.into(), title, children };
+        let list = SynElement::List(List { start: None, items: vec![ListItem { checked: None, content: vec![Inline::Text("left".into())], children: vec![] }] });
+        assert_eq!(file.get_elements(), &vec![
+            container("details", vec![Inline::Text("Show ".into()), Inline::Strong(vec![Inline::Text("more".into())])], vec![
+                paragraph("Hidden"),
+                container("columns", vec![], vec![
+                    container("column", vec![], vec![list]),
+                    SynElement::Code { lang: "".into(), source: ":::".into() },
+                ]),
+            ]),
+            paragraph(":::note"),
+        ]);
+
+        assert_eq!(file.generate_html(), concat!(
+            "<details><summary>Show <strong>more</strong></summary><p>Hidden</p>\n",
+            "<div class='columns'><div class='column'><ul><li>left</li></ul></div>\n<pre><code>:::</code></pre></div></details>\n",
+            "<p>:::note</p>",
+        ));
+        assert_eq!(file.get_elements()[0].generate_line(), ":::details Show **more**\nHidden\n\n:::columns\n:::column\n- left\n:::\n\n```\n:::\n```\n:::\n:::");
+    }
+
+    #[test]
+    fn container_errors() {
+        let recover = ParseOptions { max_container_depth: 1, ..recover() };
+
+        assert!(matches!(parse(":::note\ntext\n", &ParseOptions::default()), Err(SynError::UnclosedBlock { line: 6, .. })));
+        assert_eq!(parse("text\n:::\n", &ParseOptions::default()).unwrap_err().to_string(), "7:1: malformed container: `:::` doesn't close any container");
+
+        let (file, diagnostics) = parse(":::a\n:::b\ninner\n:::\n:::\n", &recover).unwrap();
+        assert_eq!(file.get_elements(), &vec![SynElement::Container { name: "a".into(), title: vec![], children: vec![paragraph("inner")] }]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "malformed container: containers are nested more than 1 deep");
+
+        let (file, diagnostics) = parse("> > deep\n", &recover).unwrap();
+        assert_eq!(file.get_elements(), &vec![SynElement::Quote { children: vec![paragraph("> deep")], attribution: None, cite: None }]);
+        assert_eq!(diagnostics[0].message, "malformed quote: quotes are nested more than 1 deep");
+        let err = parse(&">".repeat(100_000), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, SynError::MalformedQuote { line: 6, .. }));
+
+        let (file, diagnostics) = parse("- a\n  - b\n    - c\n", &recover).unwrap();
+        let inner = List { start: None, items: vec![ListItem { checked: None, content: vec![Inline::Text("b - c".into())], children: vec![] }] };
+        let outer = List { start: None, items: vec![ListItem { checked: None, content: vec![Inline::Text("a".into())], children: vec![inner] }] };
+        assert_eq!(file.get_elements(), &vec![SynElement::List(outer)]);
+        assert_eq!(diagnostics[0].to_string(), "8:5: error: malformed list: lists are nested more than 1 deep");
+        let nested = (0..2_000).map(|i| format!("{}- a\n", " ".repeat(i))).collect::<String>();
+        let err = parse(&nested, &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, SynError::MalformedList { line: 23, column: 18, .. }));
+    }
+
+    #[test]
+    fn callouts() {
+        let source = post("> [!WARNING]\n> Back up _first_.\n\n> [!bug] Known **issue**\n>\n> - it crashes\n\n> [!not a callout]\n");
+        let file = SynFile::from_str(&source).unwrap();
+        let list = SynElement::List(List { start: None, items: vec![ListItem { checked: None, content: vec![Inline::Text("it crashes".into())], children: vec![] }] });
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::Callout { kind: "warning".into(), title: vec![], children: vec![
+                SynElement::Text(vec![Inline::Text("Back up ".into()), Inline::Emphasis(vec![Inline::Text("first".into())]), Inline::Text(".".into())]),
+            ] },
+            SynElement::Callout { kind: "bug".into(), title: vec![Inline::Text("Known ".into()), Inline::Strong(vec![Inline::Text("issue".into())])], children: vec![list] },
+            SynElement::Quote { children: vec![paragraph("[!not a callout]")], attribution: None, cite: None },
+        ]);
+
+        let mut options = RenderOptions::default();
+        assert_eq!(file.generate_html_with(&options), concat!(
+            "<div class='callout callout-warning' role='note'><p class='callout-title'><span class='callout-icon' aria-hidden='true'>\u{26a0}\u{fe0f}</span> Warning</p>",
+            "<p>Back up <em>first</em>.</p></div>\n",
+            "<div class='callout callout-bug' role='note'><p class='callout-title'>Known <strong>issue</strong></p><ul><li>it crashes</li></ul></div>\n",
+            "<blockquote><p>[!not a callout]</p></blockquote>",
+        ));
+
+        options.callouts.insert("bug".into(), CalloutKind { label: "Bug".into(), icon: "<svg></svg>".into(), role: "alert".into() });
+        assert_eq!(file.get_elements()[1].generate_tag_with(&options), concat!(
+            "<div class='callout callout-bug' role='alert'><p class='callout-title'><span class='callout-icon' aria-hidden='true'><svg></svg></span> ",
+            "Known <strong>issue</strong></p><ul><li>it crashes</li></ul></div>",
+        ));
+        assert_eq!(file.get_elements()[0].generate_line(), "> [!WARNING]\n>\n> Back up _first_.");
+        assert_eq!(file.get_elements()[2].generate_line(), "> \\[!not a callout\\]");
+    }
+
+    #[test]
+    fn footnotes() {
+        let source = post("First[^b], then[^a] and[^b] again.\n\n[^a]: The _a_ note\ncontinued.\n\n> [^b]: Quoted, see[^c].\n\n[^c]:\n");
+        let file = SynFile::from_str(&source).unwrap();
+        let footnote = |id: &str, content: Vec<Inline>| SynElement::Footnote { id: id.into(), content };
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::Text(vec![
+                Inline::Text("First".into()), Inline::FootnoteRef("b".into()), Inline::Text(", then".into()), Inline::FootnoteRef("a".into()),
+                Inline::Text(" and".into()), Inline::FootnoteRef("b".into()), Inline::Text(" again.".into()),
+            ]),
+            footnote("a", vec![Inline::Text("The ".into()), Inline::Emphasis(vec![Inline::Text("a".into())]), Inline::Text(" note continued.".into())]),
+            SynElement::Quote { children: vec![footnote("b", vec![Inline::Text("Quoted, see".into()), Inline::FootnoteRef("c".into()), Inline::Text(".".into())])], attribution: None, cite: None },
+            footnote("c", vec![]),
+        ]);
+
+        assert_eq!(file.generate_html(), concat!(
+            "<p>First<sup class='footnote-ref' id='fnref-1'><a href='#fn-1'>1</a></sup>, then<sup class='footnote-ref' id='fnref-2'><a href='#fn-2'>2</a></sup> ",
+            "and<sup class='footnote-ref' id='fnref-1-2'><a href='#fn-1'>1</a></sup> again.</p>\n",
+            "<blockquote></blockquote>\n",
+            "<section class='footnotes' role='doc-endnotes'><ol>",
+            "<li id='fn-1'>Quoted, see<sup class='footnote-ref' id='fnref-3'><a href='#fn-3'>3</a></sup>. ",
+            "<a href='#fnref-1' class='footnote-back'>\u{21a9}</a> <a href='#fnref-1-2' class='footnote-back'>\u{21a9}<sup>2</sup></a></li>",
+            "<li id='fn-2'>The <em>a</em> note continued. <a href='#fnref-2' class='footnote-back'>\u{21a9}</a></li>",
+            "<li id='fn-3'> <a href='#fnref-3' class='footnote-back'>\u{21a9}</a></li>",
+            "</ol></section>",
+        ));
+
+        let options = RenderOptions { sidenotes: true, ..RenderOptions::default() };
+        assert_eq!(file.generate_html_with(&options).lines().next().unwrap(), concat!(
+            "<p>First<sup class='sidenote-ref' id='fnref-1'>1</sup><small class='sidenote' id='fn-1'><sup>1</sup> Quoted, see",
+            "<sup class='sidenote-ref' id='fnref-2'>2</sup><small class='sidenote' id='fn-2'><sup>2</sup> </small>.</small>, then",
+            "<sup class='sidenote-ref' id='fnref-3'>3</sup><small class='sidenote' id='fn-3'><sup>3</sup> The <em>a</em> note continued.</small> ",
+            "and<sup class='sidenote-ref' id='fnref-1-2'><a href='#fn-1'>1</a></sup> again.</p>",
+        ));
+        assert!(!file.generate_html_with(&options).contains("<section"));
+
+        // Rendered on their own, references have nothing to point to.
+        assert_eq!(file.get_elements()[0].generate_tag(), "<p>First[^b], then[^a] and[^b] again.</p>");
+        assert_eq!(file.get_elements()[1].generate_line(), "[^a]: The _a_ note continued.");
+        assert_eq!(file.get_elements()[3].generate_line(), "[^c]:");
+        assert_eq!(SynElement::Text(Inline::parse("[^a]: not a definition")).generate_line(), "[^a]\\: not a definition");
+    }
+
+    #[test]
+    fn footnote_errors() {
+        assert_eq!(parse("See[^x].\n", &ParseOptions::default()).unwrap_err().to_string(), "6:1: footnote [^x] is never defined");
+        assert_eq!(parse("text\n\n  [^x]: unused\n", &ParseOptions::default()).unwrap_err().to_string(), "8:3: footnote [^x] is never referenced");
+
+        let (_, diagnostics) = parse("[^a]: one\n\nSee[^a] and[^b][^b].\n\n[^a]: two\n", &recover()).unwrap();
+        let messages = diagnostics.iter().map(|diagnostic| (diagnostic.span.line, diagnostic.message.as_str())).collect::<Vec<_>>();
+        assert_eq!(messages, vec![(8, "footnote [^b] is never defined"), (10, "footnote [^a] is defined more than once")]);
+    }
+
+    #[test]
+    fn math() {
+        let source = post("Energy is $E = mc^2$.\n\n$$\n\\sum_{n=0}^{N} x_n\n$$\n");
+        let file = SynFile::from_str(&source).unwrap();
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::Text(vec![Inline::Text("Energy is ".into()), Inline::Math("E = mc^2".into()), Inline::Text(".".into())]),
+            SynElement::Math { source: "\\sum_{n=0}^{N} x_n".into() },
+        ]);
+        assert_eq!(file.generate_html(), concat!(
+            "<p>Energy is <math><semantics><mrow><mi>E</mi><mo>=</mo><mi>m</mi><msup><mi>c</mi><mn>2</mn></msup></mrow>",
+            "<annotation encoding='application/x-tex'>E = mc^2</annotation></semantics></math>.</p>\n",
+            "<math display='block'><semantics><mrow><munderover><mo>\u{2211}</mo><mrow><mi>n</mi><mo>=</mo><mn>0</mn></mrow><mrow><mi>N</mi></mrow></munderover>",
+            "<msub><mi>x</mi><mi>n</mi></msub></mrow><annotation encoding='application/x-tex'>\\sum_{n=0}^{N} x_n</annotation></semantics></math>",
+        ));
+        assert_eq!(file.get_elements()[1].generate_line(), "$$\n\\sum_{n=0}^{N} x_n\n$$");
+        assert_eq!(paragraph("$$").generate_line(), "\\$\\$");
+    }
+
+    #[test]
+    fn math_errors() {
+        assert_eq!(parse("Some\n  _$\\matrix{x}$_\n", &ParseOptions::default()).unwrap_err().to_string(), "6:1: malformed math: unsupported command `\\matrix`");
+        assert!(matches!(parse("$$\nx\n", &ParseOptions::default()), Err(SynError::UnclosedBlock { line: 6, .. })));
+
+        let (file, diagnostics) = parse("  $$\nx^1^2\n$$\n", &recover()).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].to_string(), "6:3: error: malformed math: double superscript");
+        assert_eq!(file.generate_html(), "<math display='block'><merror><mtext>double superscript</mtext></merror></math>");
+    }
+
+    #[test]
+    fn media() {
+        let source = post(concat!(
+            ".video talk.webm|Watch <it> elsewhere. source=talk.MP4 poster=talk.jpg track=\"talk.en.vtt;en;English (US)\" track=talk.de.vtt;de muted=true autoplay=true\n\n",
+            ".audio javascript:alert(1)\n",
+        ));
+        let file = SynFile::from_str(&source).unwrap();
+        let video = Media {
+            kind: MediaKind::Video,
+            sources: vec!["talk.webm".into(), "talk.MP4".into()],
+            poster: Some("talk.jpg".into()),
+            tracks: vec![
+                Track { path: "talk.en.vtt".into(), lang: "en".into(), label: "English (US)".into() },
+                Track { path: "talk.de.vtt".into(), lang: "de".into(), label: "".into() },
+            ],
+            autoplay: true,
+            looping: false,
+            muted: true,
+            fallback: "Watch <it> elsewhere.".into(),
+        };
+        let audio = Media { kind: MediaKind::Audio, sources: vec!["javascript:alert(1)".into()], poster: None, tracks: vec![], autoplay: false, looping: false, muted: false, fallback: "".into() };
+        assert_eq!(file.get_elements(), &vec![SynElement::Media(video), SynElement::Media(audio)]);
+
+        assert_eq!(file.generate_html(), concat!(
+            "<video controls preload='metadata' poster='talk.jpg' autoplay muted><source src='talk.webm' type='video/webm'><source src='talk.MP4' type='video/mp4'>",
+            "<track kind='captions' src='talk.en.vtt' srclang='en' label='English (US)' default><track kind='captions' src='talk.de.vtt' srclang='de' label='de'>",
+            "<p>Watch &lt;it&gt; elsewhere. <a href='talk.webm'>Download the video</a>.</p></video>\n",
+            "<audio controls preload='metadata'><source src='#'><p>Your browser can't play this audio. <a href='#'>Download the audio</a>.</p></audio>",
+        ));
+        assert_eq!(file.get_elements()[0].generate_line(),
+            ".video talk.webm|Watch <it> elsewhere. source=talk.MP4 poster=talk.jpg track=\"talk.en.vtt;en;English (US)\" track=talk.de.vtt;de autoplay=true muted=true");
+        assert_eq!(file.get_elements()[1].generate_line(), ".audio javascript:alert(1)");
+    }
+
+    #[test]
+    fn invalid_media() {
+        assert_eq!(parse_error(".video |fallback\n"), "malformed .video directive: missing source path");
+        assert_eq!(parse_error(".video a.mp4 source= \n"), "malformed .video directive: missing source path");
+        assert_eq!(parse_error(".video a.mp4 source=\" \"\n"), "malformed .video directive: missing source path");
+        assert_eq!(parse_error(".audio a.mp3 poster=cover.jpg\n"), "malformed .audio directive: audio can't have a poster");
+        assert_eq!(parse_error(".video a.mp4 track=a.srt;en\n"), "malformed .video directive: track 'a.srt' isn't a WebVTT (.vtt) file");
+        assert_eq!(parse_error(".video a.mp4 track=a.vtt\n"), "malformed .video directive: track 'a.vtt' has no language, as in `track=a.vtt;en`");
+        assert_eq!(parse_error(".audio a.mp3 loop=yes\n"), "malformed .audio directive: loop should be true or false, not 'yes'");
+        assert_eq!(parse_error(".audio a.mp3|b|c\n"), "malformed .audio directive: expected 1 to 2 sections separated by '|' (path|fallback), found 3");
+    }
+
+    #[test]
+    fn embeds() {
+        let source = post(".embed youtube|dQw4w9WgXcQ|thumbs/talk.jpg|Our <talk>\n\n.embed mastodon|mastodon.social/@syn/1234\n\n.embed peertube|abc||Elsewhere\n");
+        let file = SynFile::from_str(&source).unwrap();
+        let embed = |provider: &str, id: &str, thumbnail: &str, title: &str| SynElement::Embed { provider: provider.into(), id: id.into(), thumbnail: thumbnail.into(), title: title.into() };
+        assert_eq!(file.get_elements(), &vec![
+            embed("youtube", "dQw4w9WgXcQ", "thumbs/talk.jpg", "Our <talk>"),
+            embed("mastodon", "mastodon.social/@syn/1234", "", ""),
+            embed("peertube", "abc", "", "Elsewhere"),
+        ]);
+
+        let mut options = RenderOptions::default();
+        assert_eq!(file.generate_html_with(&options), concat!(
+            "<div class='embed embed-youtube' data-embed-src='https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ?autoplay=1'>",
+            "<a class='embed-link' href='https://www.youtube.com/watch?v=dQw4w9WgXcQ'><img src='thumbs/talk.jpg' alt='' loading='lazy'>",
+            "<span class='embed-title'>Our &lt;talk&gt;</span><span class='embed-action'>Watch on YouTube</span></a></div>\n",
+            "<div class='embed embed-mastodon' data-embed-src='https://mastodon.social/@syn/1234/embed'>",
+            "<a class='embed-link' href='https://mastodon.social/@syn/1234'><span class='embed-action'>View on Mastodon</span></a></div>\n",
+            "<div class='embed embed-peertube'><span class='embed-title'>Elsewhere</span></div>",
+        ));
+
+        options.embeds.insert("peertube".into(), EmbedProvider {
+            link: "https://videos.example/w/{id}".into(),
+            frame: "https://videos.example/videos/embed/{id}".into(),
+            action: "Watch on PeerTube".into(),
+        });
+        assert_eq!(file.get_elements()[2].generate_tag_with(&options), concat!(
+            "<div class='embed embed-peertube' data-embed-src='https://videos.example/videos/embed/abc'><a class='embed-link' href='https://videos.example/w/abc'>",
+            "<span class='embed-title'>Elsewhere</span><span class='embed-action'>Watch on PeerTube</span></a></div>",
+        ));
+        assert_eq!(file.get_elements()[1].generate_line(), ".embed mastodon|mastodon.social/@syn/1234");
+        assert_eq!(file.get_elements()[2].generate_line(), ".embed peertube|abc||Elsewhere");
+    }
+
+    #[test]
+    fn invalid_embeds() {
+        assert_eq!(parse_error(".embed YouTube|abc\n"), "malformed .embed directive: 'YouTube' isn't a provider name");
+        assert_eq!(parse_error(".embed youtube|\n"), "malformed .embed directive: missing ID");
+        assert_eq!(parse_error(".embed youtube|abc?x=<script>\n"), "malformed .embed directive: 'abc?x=<script>' isn't a valid ID");
+        assert_eq!(parse_error(".embed youtube\n"), "malformed .embed directive: expected 2 to 4 sections separated by '|' (provider|id|thumbnail|title), found 1");
+    }
+
+    #[test]
+    fn definition_lists() {
+        let source = post("; Crate\n: A unit of\ncompilation.\n: A box.\n; Trait\n; Interface\n: Shared _behaviour_.\n\n: not a definition\n");
+        let file = SynFile::from_str(&source).unwrap();
+        let text = |text: &str| vec![Inline::Text(text.into())];
+        assert_eq!(file.get_elements(), &vec![
+            SynElement::DefinitionList(vec![
+                Definition { term: text("Crate"), details: vec![text("A unit of compilation."), text("A box.")] },
+                Definition { term: text("Trait"), details: vec![] },
+                Definition { term: text("Interface"), details: vec![vec![Inline::Text("Shared ".into()), Inline::Emphasis(text("behaviour")), Inline::Text(".".into())]] },
+            ]),
+            paragraph(": not a definition"),
+        ]);
+        assert_eq!(file.generate_html(), concat!(
+            "<dl><dt>Crate</dt><dd>A unit of compilation.</dd><dd>A box.</dd><dt>Trait</dt><dt>Interface</dt><dd>Shared <em>behaviour</em>.</dd></dl>\n",
+            "<p>: not a definition</p>",
+        ));
+        assert_eq!(file.get_elements()[0].generate_line(), "; Crate\n: A unit of compilation.\n: A box.\n; Trait\n; Interface\n: Shared _behaviour_.");
+        assert_eq!(file.get_elements()[1].generate_line(), "\\: not a definition");
+        assert_eq!(paragraph("; x").generate_line(), "\\; x");
+    }
+
+    #[test]
+    fn glossary() {
+        let source = post("Crates and a crate, in HTML. [A crate](/c) and `crate`: a crate.\n\n; crate\n: A unit of compilation.\n\nHTMLX is not HTML, and HTML is not a crate.\n");
+        let file = SynFile::from_str(&source).unwrap();
+        let mut options = RenderOptions::default();
+        options.glossary.insert("HTML".into(), "HyperText Markup Language".into());
+        options.glossary.insert("crate".into(), "Overridden by the post's own definition".into());
+        let html = file.generate_html_with(&options);
+        let paragraphs = html.lines().filter(|line| line.starts_with("<p>")).collect::<Vec<_>>();
+        assert_eq!(paragraphs, vec![
+            "<p>Crates and a <dfn title='A unit of compilation.'>crate</dfn>, in <abbr title='HyperText Markup Language'>HTML</abbr>. <a href='/c'>A crate</a> and <code>crate</code>: a crate.</p>",
+            "<p>HTMLX is not HTML, and HTML is not a crate.</p>",
+        ]);
+
+        // Trusted text is HTML, where terms can't safely be wrapped.
+        options.trusted_html = true;
+        assert!(!file.generate_html_with(&options).contains("<dfn"));
+    }
+
+    #[test]
+    fn metadata_header() {
+        let source = "Title\ntag\n2024-01-01\nSummary\n---\nauthor: Jo Bloggs\ndraft: true\norder: -3\nslug: \"007\"\nnote: \"say \\\"hi\\\"\\n\"\n---\n\n#Heading\n";
+        let file = SynFile::from_str(source).unwrap();
+        let metadata = file.get_metadata();
+        assert_eq!(metadata.iter().map(|(key, _)| key).collect::<Vec<_>>(), vec!["author", "draft", "order", "slug", "note"]);
+        assert_eq!(metadata.get("author").and_then(MetadataValue::as_str), Some("Jo Bloggs"));
+        assert_eq!(metadata.get("draft").and_then(MetadataValue::as_bool), Some(true));
+        assert_eq!(metadata.get("order").and_then(MetadataValue::as_integer), Some(-3));
+        assert_eq!(metadata.get("slug"), Some(&MetadataValue::Text("007".into())));
+        assert_eq!(metadata.get("note").and_then(MetadataValue::as_str), Some("say \"hi\"\n"));
+        assert_eq!(file.get_elements(), &vec![heading(1, "Heading")]);
+        // `007` reads as text either way, so it doesn't need its quotes.
+        assert_eq!(file.to_string(), source.replace("\"007\"", "007").replace("#Heading\n", "#Heading\n\n"));
+
+        let path = temp_file("metadata.txt", source.as_bytes());
+        assert_eq!(SynFile::load_file_metadata(&path).unwrap().get_metadata(), metadata);
+    }
+
+    #[test]
+    fn metadata_is_optional() {
+        // Without the `---` fence, lines that look like metadata are the first element, as they
+        // were before metadata existed.
+        for body in ["note: remember this\n\nText\n", "\nnote: remember this\nnote: again\n\nText\n"] {
+            let file = SynFile::from_str(&format!("Title\ntag\n2024-01-01\nSummary\n{body}")).unwrap();
+            assert!(file.get_metadata().is_empty());
+            assert_eq!(file.get_elements()[0], SynElement::Text(Inline::parse(body.trim().split("\n\n").next().unwrap())));
+        }
+        let file = SynFile::from_str(&post("---\nauthor: Jo\n---\n")).unwrap();
+        assert!(file.get_metadata().is_empty());
+        assert_eq!(file.get_elements(), &vec![SynElement::LineH, paragraph("author: Jo"), SynElement::LineH]);
+
+        let err = SynFile::from_str("Title\ntag\n2024-01-01\nSummary\n---\nauthor: Jo\n---\n\n.img a.png|x|y|z\n").unwrap_err();
+        assert_eq!(err.line(), Some(9));
+
+        // A `---` block that isn't closed, or has a line that isn't `key: value`, is a rule and
+        // text, as it was before metadata existed.
+        let old = [
+            ("---\nSome text\n---\n", vec![SynElement::LineH, paragraph("Some text"), SynElement::LineH]),
+            ("---\n", vec![SynElement::LineH]),
+            ("---\nauthor: Jo\n", vec![SynElement::LineH, paragraph("author: Jo")]),
+            ("---\nauthor: Jo\n\nSome text\n---\n", vec![SynElement::LineH, paragraph("author: Jo"), paragraph("Some text"), SynElement::LineH]),
+        ];
+        for (body, elements) in old {
+            let file = SynFile::from_str(&format!("Title\ntag\n2024-01-01\nSummary\n{body}")).unwrap();
+            assert!(file.get_metadata().is_empty());
+            assert_eq!(file.get_elements(), &elements, "{body}");
+        }
+        let err = SynFile::from_str("Title\ntag\n2024-01-01\nSummary\n---\nSome text\n---\n\n.img a.png|x|y|z\n").unwrap_err();
+        assert_eq!(err.line(), Some(9));
+
+        let mut file = SynFile::from_str("Title\ntag\n2024-01-01\nSummary\nhttps://example.com\n").unwrap();
+        assert!(file.get_metadata().is_empty());
+        assert_eq!(file.to_string(), post("https://example.com\n\n"));
+        file.get_metadata_mut().insert("draft".into(), MetadataValue::Bool(false));
+        assert_eq!(file.get_metadata_mut().insert("draft".into(), MetadataValue::Text("true".into())), Some(MetadataValue::Bool(false)));
+        assert_eq!(file.to_string(), "Title\ntag\n2024-01-01\nSummary\n---\ndraft: \"true\"\n---\n\nhttps://example.com\n\n");
+        assert_eq!(SynFile::from_str(&file.to_string()).unwrap(), file);
+    }
+
+    #[test]
+    fn malformed_metadata() {
+        let err = SynFile::from_str("Title\ntag\n2024-01-01\nSummary\n---\nauthor: Jo\nauthor: Al\n---\n").unwrap_err();
+        assert!(matches!(err, SynError::MalformedMetadata { line: 7, column: 1, .. }));
+        assert_eq!(err.message(), "malformed metadata: `author` is set more than once");
+
+        let source = "Title\ntag\n2024-01-01\nSummary\n---\ncover:  \"a.png\nalt: \"x\\q\"\nslug: ok\n---\n\nText\n";
+        let (file, diagnostics) = SynFile::from_str_with(source, &recover()).unwrap();
+        assert_eq!(file.get_metadata().iter().collect::<Vec<_>>(), vec![("slug", &MetadataValue::Text("ok".into()))]);
+        assert_eq!(file.get_elements(), &vec![paragraph("Text")]);
+        let found = diagnostics.iter().map(|d| (d.span.line, d.span.column, d.message.as_str())).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            (6, 9, "malformed metadata: quoted text is never closed"),
+            (7, 6, "malformed metadata: unknown escape `\\q`"),
+        ]);
     }
 }
@@ -0,0 +1,363 @@
+use crate::escape_html;
+
+
+// Types
+
+/// Converts one formula to MathML as it reads it.
+struct MathParser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+    /// How many atoms we're inside, such as `{groups}` and command arguments.
+    depth: usize,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum AtomKind {
+    Ordinary,
+    /// An operator like `\sum` or `\lim`, whose scripts go below and above it in display math.
+    Limits,
+}
+
+
+// Commands
+
+/// How deeply atoms may nest, so deeply nested math is an error rather than a stack overflow.
+const MAX_DEPTH: usize = 64;
+
+const GREEK: &[(&str, char)] = &[
+    ("alpha", '\u{3b1}'), ("beta", '\u{3b2}'), ("gamma", '\u{3b3}'), ("delta", '\u{3b4}'), ("epsilon", '\u{3f5}'),
+    ("varepsilon", '\u{3b5}'), ("zeta", '\u{3b6}'), ("eta", '\u{3b7}'), ("theta", '\u{3b8}'), ("vartheta", '\u{3d1}'),
+    ("iota", '\u{3b9}'), ("kappa", '\u{3ba}'), ("lambda", '\u{3bb}'), ("mu", '\u{3bc}'), ("nu", '\u{3bd}'),
+    ("xi", '\u{3be}'), ("pi", '\u{3c0}'), ("varpi", '\u{3d6}'), ("rho", '\u{3c1}'), ("varrho", '\u{3f1}'),
+    ("sigma", '\u{3c3}'), ("varsigma", '\u{3c2}'), ("tau", '\u{3c4}'), ("upsilon", '\u{3c5}'), ("phi", '\u{3d5}'),
+    ("varphi", '\u{3c6}'), ("chi", '\u{3c7}'), ("psi", '\u{3c8}'), ("omega", '\u{3c9}'),
+    ("infty", '\u{221e}'), ("partial", '\u{2202}'), ("nabla", '\u{2207}'), ("emptyset", '\u{2205}'), ("hbar", '\u{210f}'),
+    ("ell", '\u{2113}'), ("Re", '\u{211c}'), ("Im", '\u{2111}'), ("aleph", '\u{2135}'),
+];
+
+/// Capital Greek letters, which TeX sets upright.
+const UPRIGHT_GREEK: &[(&str, char)] = &[
+    ("Gamma", '\u{393}'), ("Delta", '\u{394}'), ("Theta", '\u{398}'), ("Lambda", '\u{39b}'), ("Xi", '\u{39e}'),
+    ("Pi", '\u{3a0}'), ("Sigma", '\u{3a3}'), ("Upsilon", '\u{3a5}'), ("Phi", '\u{3a6}'), ("Psi", '\u{3a8}'),
+    ("Omega", '\u{3a9}'),
+];
+
+const OPERATORS: &[(&str, char)] = &[
+    ("cdot", '\u{22c5}'), ("times", '\u{d7}'), ("div", '\u{f7}'), ("pm", '\u{b1}'), ("mp", '\u{2213}'),
+    ("ast", '\u{2217}'), ("circ", '\u{2218}'), ("star", '\u{22c6}'), ("oplus", '\u{2295}'), ("otimes", '\u{2297}'),
+    ("leq", '\u{2264}'), ("le", '\u{2264}'), ("geq", '\u{2265}'), ("ge", '\u{2265}'), ("neq", '\u{2260}'),
+    ("ne", '\u{2260}'), ("ll", '\u{226a}'), ("gg", '\u{226b}'), ("approx", '\u{2248}'), ("equiv", '\u{2261}'),
+    ("sim", '\u{223c}'), ("propto", '\u{221d}'), ("to", '\u{2192}'), ("rightarrow", '\u{2192}'), ("leftarrow", '\u{2190}'),
+    ("leftrightarrow", '\u{2194}'), ("Rightarrow", '\u{21d2}'), ("Leftarrow", '\u{21d0}'), ("iff", '\u{21d4}'),
+    ("mapsto", '\u{21a6}'), ("in", '\u{2208}'), ("notin", '\u{2209}'), ("subset", '\u{2282}'), ("subseteq", '\u{2286}'),
+    ("supset", '\u{2283}'), ("cup", '\u{222a}'), ("cap", '\u{2229}'), ("wedge", '\u{2227}'), ("vee", '\u{2228}'),
+    ("neg", '\u{ac}'), ("forall", '\u{2200}'), ("exists", '\u{2203}'), ("mid", '\u{2223}'), ("parallel", '\u{2225}'),
+    ("perp", '\u{22a5}'), ("ldots", '\u{2026}'), ("cdots", '\u{22ef}'), ("langle", '\u{27e8}'), ("rangle", '\u{27e9}'),
+    ("lfloor", '\u{230a}'), ("rfloor", '\u{230b}'), ("lceil", '\u{2308}'), ("rceil", '\u{2309}'),
+    ("sum", '\u{2211}'), ("prod", '\u{220f}'), ("coprod", '\u{2210}'), ("bigcup", '\u{22c3}'), ("bigcap", '\u{22c2}'),
+    ("int", '\u{222b}'), ("iint", '\u{222c}'), ("oint", '\u{222e}'),
+];
+
+/// Named functions, set upright as a word.
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "sec", "csc", "cot", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "exp", "log", "ln", "lg", "det", "dim", "deg", "arg", "ker", "gcd", "lim", "max", "min", "sup", "inf",
+];
+
+const LIMITS: &[&str] = &["sum", "prod", "coprod", "bigcup", "bigcap", "lim", "max", "min", "sup", "inf"];
+
+const ACCENTS: &[(&str, char)] = &[
+    ("hat", '^'), ("bar", '\u{af}'), ("overline", '\u{203e}'), ("vec", '\u{2192}'), ("dot", '\u{2d9}'),
+    ("ddot", '\u{a8}'), ("tilde", '\u{2dc}'),
+];
+
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"), (":", "0.2222em"), (";", "0.2778em"), ("!", "-0.1667em"), (" ", "0.3333em"),
+    ("quad", "1em"), ("qquad", "2em"),
+];
+
+
+// Implementations
+
+/// Renders LaTeX math as a `<math>` element, keeping the TeX as an annotation. Math using
+/// something we don't support renders as an `<merror>` saying what.
+pub(crate) fn render(source: &str, display: bool) -> String {
+    let attributes = match display {
+        true => " display='block'",
+        false => "",
+    };
+    match convert(source, display) {
+        Ok(mathml) => format!(
+            "<math{attributes}><semantics><mrow>{mathml}</mrow><annotation encoding='application/x-tex'>{}</annotation></semantics></math>",
+            escape_html(source.trim()),
+        ),
+        Err(message) => format!("<math{attributes}><merror><mtext>{}</mtext></merror></math>", escape_html(&message)),
+    }
+}
+
+
+/// Checks that math only uses the LaTeX we support, returning what's wrong if it doesn't.
+pub(crate) fn validate(source: &str) -> Result<(), String> {
+    convert(source, false).map(|_| ())
+}
+
+
+fn convert(source: &str, display: bool) -> Result<String, String> {
+    let mut parser = MathParser { chars: source.chars().collect(), pos: 0, display, depth: 0 };
+    parser.parse_row(None)
+}
+
+
+impl MathParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+
+    /// Parses atoms and their scripts until the end of the source, or past `end` if there is one.
+    fn parse_row(&mut self, end: Option<char>) -> Result<String, String> {
+        let mut row = String::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return match end {
+                    Some(end) => Err(format!("missing closing `{end}`")),
+                    None => Ok(row),
+                },
+                Some(c) if Some(c) == end => {
+                    self.pos += 1;
+                    return Ok(row);
+                },
+                Some('}') => return Err("`}` without a matching `{`".into()),
+                // A script with nothing before it goes on an empty base.
+                Some('^' | '_') => row.push_str(&self.parse_scripts("<mrow></mrow>".into(), AtomKind::Ordinary)?),
+                Some(c) => {
+                    let (atom, kind) = self.parse_atom(c, false)?;
+                    row.push_str(&self.parse_scripts(atom, kind)?);
+                },
+            }
+        }
+    }
+
+
+    /// Attaches any `_sub` and `^sup` following an atom to it.
+    fn parse_scripts(&mut self, base: String, kind: AtomKind) -> Result<String, String> {
+        let (mut sub, mut sup) = (None, None);
+        loop {
+            self.skip_whitespace();
+            let (script, name) = match self.peek() {
+                Some('_') => (&mut sub, "subscript"),
+                Some('^') => (&mut sup, "superscript"),
+                _ => break,
+            };
+            if script.is_some() {
+                return Err(format!("double {name}"));
+            }
+            self.pos += 1;
+            *script = Some(self.parse_argument(name)?);
+        }
+
+        let limits = kind == AtomKind::Limits && self.display;
+        Ok(match (sub, sup, limits) {
+            (None, None, _) => base,
+            (Some(sub), None, false) => format!("<msub>{base}{sub}</msub>"),
+            (None, Some(sup), false) => format!("<msup>{base}{sup}</msup>"),
+            (Some(sub), Some(sup), false) => format!("<msubsup>{base}{sub}{sup}</msubsup>"),
+            (Some(sub), None, true) => format!("<munder>{base}{sub}</munder>"),
+            (None, Some(sup), true) => format!("<mover>{base}{sup}</mover>"),
+            (Some(sub), Some(sup), true) => format!("<munderover>{base}{sub}{sup}</munderover>"),
+        })
+    }
+
+
+    /// Parses the argument of a command or script: a `{group}`, or otherwise a single atom.
+    fn parse_argument(&mut self, what: &str) -> Result<String, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None | Some('}' | '^' | '_') => Err(format!("{what} is missing its argument")),
+            Some(c) => self.parse_atom(c, true).map(|(atom, _)| atom),
+        }
+    }
+
+
+    /// Parses a letter, number, operator, `{group}` or command. Numbers in an argument are a
+    /// single digit, like in TeX, so `x^23` is `x` squared, then 3. `c` is the next character,
+    /// which the caller has already peeked at.
+    fn parse_atom(&mut self, c: char, in_argument: bool) -> Result<(String, AtomKind), String> {
+        if self.depth == MAX_DEPTH {
+            return Err("math is nested too deeply".into());
+        }
+        self.depth += 1;
+        let atom = self.parse_atom_at_depth(c, in_argument);
+        self.depth -= 1;
+        atom
+    }
+
+
+    fn parse_atom_at_depth(&mut self, c: char, in_argument: bool) -> Result<(String, AtomKind), String> {
+        self.pos += 1;
+        let atom = match c {
+            '\\' => return self.parse_command(),
+            '{' => format!("<mrow>{}</mrow>", self.parse_row(Some('}'))?),
+            c if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(next) = self.peek().filter(|_| !in_argument) {
+                    let decimal_point = next == '.' && self.chars.get(self.pos + 1).is_some_and(char::is_ascii_digit);
+                    if !next.is_ascii_digit() && !decimal_point {
+                        break;
+                    }
+                    number.push(next);
+                    self.pos += 1;
+                }
+                format!("<mn>{number}</mn>")
+            },
+            c if c.is_alphabetic() => format!("<mi>{c}</mi>"),
+            '-' => "<mo>\u{2212}</mo>".into(),
+            '*' => "<mo>\u{2217}</mo>".into(),
+            '\'' => "<mo>\u{2032}</mo>".into(),
+            '~' => "<mspace width='0.3333em'/>".into(),
+            '&' | '#' | '%' | '$' => return Err(format!("`{c}` isn't supported in math")),
+            c => format!("<mo>{}</mo>", escape_html(&c.to_string())),
+        };
+        Ok((atom, AtomKind::Ordinary))
+    }
+
+
+    /// Parses a command, just after its backslash.
+    fn parse_command(&mut self) -> Result<(String, AtomKind), String> {
+        let len = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_alphabetic()).count().max(1);
+        let Some(name) = self.chars.get(self.pos..self.pos + len).map(|name| name.iter().collect::<String>()) else {
+            return Err("`\\` at the end of the math".into());
+        };
+        self.pos += len;
+
+        let lookup = |table: &[(&str, char)]| table.iter().find(|(other, _)| *other == name).map(|(_, c)| *c);
+        let kind = match LIMITS.contains(&name.as_str()) {
+            true => AtomKind::Limits,
+            false => AtomKind::Ordinary,
+        };
+        let atom = match name.as_str() {
+            "frac" => {
+                let numerator = self.parse_argument("`\\frac`")?;
+                format!("<mfrac>{numerator}{}</mfrac>", self.parse_argument("`\\frac`")?)
+            },
+            "sqrt" => {
+                self.skip_whitespace();
+                match self.peek() {
+                    Some('[') => {
+                        self.pos += 1;
+                        let index = self.parse_row(Some(']'))?;
+                        format!("<mroot>{}<mrow>{index}</mrow></mroot>", self.parse_argument("`\\sqrt`")?)
+                    },
+                    _ => format!("<msqrt>{}</msqrt>", self.parse_argument("`\\sqrt`")?),
+                }
+            },
+            "text" => format!("<mtext>{}</mtext>", escape_html(&self.parse_text("`\\text`")?)),
+            "mathrm" => format!("<mi mathvariant='normal'>{}</mi>", escape_html(&self.parse_text("`\\mathrm`")?)),
+            "mathbf" => format!("<mi mathvariant='bold'>{}</mi>", escape_html(&self.parse_text("`\\mathbf`")?)),
+            "left" | "right" => {
+                self.skip_whitespace();
+                match self.peek() {
+                    Some('.') => {
+                        self.pos += 1;
+                        "<mrow></mrow>".into()
+                    },
+                    Some(c @ ('(' | ')' | '[' | ']' | '|' | '/' | '\\')) => self.parse_atom(c, true)?.0,
+                    _ => return Err(format!("`\\{name}` is missing its delimiter")),
+                }
+            },
+            "{" | "}" | "|" | "$" | "%" | "&" | "#" | "_" => format!("<mo>{}</mo>", escape_html(&name)),
+            _ => if let Some(c) = lookup(GREEK) {
+                format!("<mi>{c}</mi>")
+            } else if let Some(c) = lookup(UPRIGHT_GREEK) {
+                format!("<mi mathvariant='normal'>{c}</mi>")
+            } else if let Some(c) = lookup(OPERATORS) {
+                format!("<mo>{c}</mo>")
+            } else if FUNCTIONS.contains(&name.as_str()) {
+                format!("<mi>{name}</mi>")
+            } else if let Some(c) = lookup(ACCENTS) {
+                format!("<mover accent='true'>{}<mo>{c}</mo></mover>", self.parse_argument(&format!("`\\{name}`"))?)
+            } else if let Some((_, width)) = SPACES.iter().find(|(other, _)| *other == name) {
+                format!("<mspace width='{width}'/>")
+            } else {
+                return Err(format!("unsupported command `\\{name}`"));
+            },
+        };
+        Ok((atom, kind))
+    }
+
+
+    /// Reads the `{...}` argument of a command like `\text` as plain text.
+    fn parse_text(&mut self, what: &str) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            return Err(format!("{what} needs its argument in braces"));
+        }
+        self.pos += 1;
+        let mut text = String::new();
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Ok(text),
+                '}' => depth -= 1,
+                _ => {},
+            }
+            text.push(c);
+        }
+        Err("missing closing `}`".into())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_latex() {
+        assert_eq!(convert("x^2 + 1", false).unwrap(), "<msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1</mn>");
+        assert_eq!(convert("a_{i}^{2}", false).unwrap(), "<msubsup><mi>a</mi><mrow><mi>i</mi></mrow><mrow><mn>2</mn></mrow></msubsup>");
+        assert_eq!(convert("x^23.5", false).unwrap(), "<msup><mi>x</mi><mn>2</mn></msup><mn>3.5</mn>");
+        assert_eq!(convert("\\frac{\\alpha}{2\\pi} - \\Omega", false).unwrap(), concat!(
+            "<mfrac><mrow><mi>\u{3b1}</mi></mrow><mrow><mn>2</mn><mi>\u{3c0}</mi></mrow></mfrac>",
+            "<mo>\u{2212}</mo><mi mathvariant='normal'>\u{3a9}</mi>",
+        ));
+        assert_eq!(convert("\\sqrt[3]{x} \\leq \\text{a & b}", false).unwrap(), "<mroot><mrow><mi>x</mi></mrow><mrow><mn>3</mn></mrow></mroot><mo>\u{2264}</mo><mtext>a &amp; b</mtext>");
+        assert_eq!(convert("\\left( \\hat x \\right.", false).unwrap(), "<mo>(</mo><mover accent='true'><mi>x</mi><mo>^</mo></mover><mrow></mrow>");
+    }
+
+    #[test]
+    fn limits_in_display_math() {
+        assert_eq!(convert("\\sum_{n=0}^N x", false).unwrap(), "<msubsup><mo>\u{2211}</mo><mrow><mi>n</mi><mo>=</mo><mn>0</mn></mrow><mi>N</mi></msubsup><mi>x</mi>");
+        assert_eq!(convert("\\lim_n", true).unwrap(), "<munder><mi>lim</mi><mi>n</mi></munder>");
+        assert_eq!(render("x<y", true), concat!(
+            "<math display='block'><semantics><mrow><mi>x</mi><mo>&lt;</mo><mi>y</mi></mrow>",
+            "<annotation encoding='application/x-tex'>x&lt;y</annotation></semantics></math>",
+        ));
+    }
+
+    #[test]
+    fn unsupported_math() {
+        assert_eq!(validate("\\begin{matrix}"), Err("unsupported command `\\begin`".into()));
+        assert_eq!(validate("{x"), Err("missing closing `}`".into()));
+        assert_eq!(validate("x}"), Err("`}` without a matching `{`".into()));
+        assert_eq!(validate("x^1^2"), Err("double superscript".into()));
+        assert_eq!(validate("\\frac{1}"), Err("`\\frac` is missing its argument".into()));
+        assert_eq!(validate("a & b"), Err("`&` isn't supported in math".into()));
+        assert_eq!(validate("x\\"), Err("`\\` at the end of the math".into()));
+        assert_eq!(validate(&"{".repeat(100_000)), Err("math is nested too deeply".into()));
+        assert_eq!(validate(&"\\sqrt".repeat(100_000)), Err("math is nested too deeply".into()));
+        assert!(validate(&format!("{}x{}", "{".repeat(MAX_DEPTH - 1), "}".repeat(MAX_DEPTH - 1))).is_ok());
+        assert_eq!(render("\\foo", false), "<math><merror><mtext>unsupported command `\\foo`</mtext></merror></math>");
+    }
+}